@@ -1,71 +1,267 @@
-use crate::{get_bot_storage, handlers::*, text, MyDialogue};
 use crate::{handlers::StartCommand, State};
+use crate::{handlers::*, text, HandlerResult, MyDialogue, MyStorage};
 use dptree::case;
 use std::error::Error;
 use teloxide::dispatching::dialogue::GetChatId;
 use teloxide::dispatching::UpdateFilterExt;
 use teloxide::prelude::*;
+use teloxide::requests::HasPayload;
+use teloxide::RequestError;
 use teloxide::{
-    dispatching::{
-        dialogue::{self, ErasedStorage},
-        UpdateHandler,
-    },
-    types::Update,
+    dispatching::UpdateHandler,
+    types::{Update, UpdateKind, UserId},
 };
 
-async fn check_if_the_state_is_ok(update: Update) -> bool {
+/*
+    Whose dialogue is it?
+
+    In a private chat it's simple, the chat is the user. But in a group everyone talks at the same
+    time, so if the dialogue was per chat, one user could send the first number, and another one
+    the second. So in groups every user has their own dialogue, per (group, user). And in forums
+    (supergroups with topics) every topic is like a separate chat, so it's per (group, topic, user).
+
+    teloxide keeps the dialogues by a ChatId, so for the groups we make up one from all these ids.
+    Telegram's ids are all smaller than 2^52, and these keys are below -2^62, so they never clash
+    with a real chat. The private chats keep their own id, so the states in redis stay where they were
+*/
+
+pub fn dialogue_key(chat_id: ChatId, user_id: Option<UserId>, topic: Option<i32>) -> ChatId {
+    let Some(user_id) = user_id.filter(|_| !chat_id.is_user()) else {
+        return chat_id; // Private chats, and channels, where the posts have no user
+    };
+    // FNV-1a, a very simple hash. Not the one from std, it can change between the versions of
+    // Rust, and the keys are saved in redis, so they have to stay the same forever
+    let mut bytes = [chat_id.0.to_le_bytes(), user_id.0.to_le_bytes()].concat();
+    if let Some(topic) = topic {
+        // Only for the topics, so the keys outside of forums stay the same as before them
+        bytes.extend(topic.to_le_bytes());
+    }
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    ChatId(i64::MIN | (hash >> 2) as i64)
+}
+
+pub fn get_update_topic(update: &Update) -> Option<i32> {
+    // For the button presses it's the topic of the message with the buttons
+    match &update.kind {
+        UpdateKind::Message(message) | UpdateKind::EditedMessage(message) => get_topic(message),
+        UpdateKind::CallbackQuery(call) => get_topic(call.message.as_ref()?),
+        _ => None,
+    }
+}
+
+pub fn get_dialogue_key(update: &Update) -> Option<ChatId> {
+    // The key of the dialogue that this update belongs to
+    Some(dialogue_key(
+        update.chat_id()?,
+        update.user().map(|user| user.id),
+        get_update_topic(update),
+    ))
+}
+
+async fn the_state_is_broken(update: Update, storage: MyStorage) -> bool {
     // This function doesn't have anything to do with tests, but i thought i would put it here,
     // because i've encountered that if you update the state, and the user is on that
     // state, it just errors out, softlocking the user. Very bad.
-    let Some(chat_id) = update.chat_id() else {
-        return true;
+    // The migrations (see migrations.rs) fix most of it, but not a state that got broken, or
+    // that was saved by a newer version of the bot, that was rolled back
+    let Some(key) = get_dialogue_key(&update) else {
+        return false;
     };
-    let dialogue = MyDialogue::new(get_bot_storage().await, chat_id);
-    match dialogue.get().await {
-        Ok(_) => true,
-        Err(_) => {
-            // This error happens if redis has a state saved for the user, but that state
-            // doesn't fit into anything that State has, so it just errors out. Very bad.
-            let bot = Bot::from_env();
-            bot.send_message(chat_id, text::SORRY_BOT_UPDATED)
-                .await
-                .unwrap();
-            dialogue.update(State::default()).await.unwrap();
-            return false;
+    // The storage is the one from the dependencies, so it's redis for the real bot, and the
+    // in-memory storage for tests
+    MyDialogue::new(storage, key).get().await.is_err()
+}
+
+fn apologize<B: Requester>(bot: &B, chat_id: ChatId, topic: Option<i32>) -> B::SendMessage {
+    // Into the same topic, if the update came from one
+    let mut request = bot.send_message(chat_id, text::SORRY_BOT_UPDATED);
+    request.payload_mut().message_thread_id = topic;
+    request
+}
+
+async fn reset_the_broken_state<B: Requester<Err = RequestError>>(
+    bot: B,
+    update: Update,
+    storage: MyStorage,
+) -> HandlerResult {
+    // The user gets an apology, and starts over. The bot is the one from the dependencies too,
+    // so in tests the apology is intercepted, like everything else
+    let chat_id = update.chat_id().ok_or("The broken state has no chat")?;
+    let key = get_dialogue_key(&update).ok_or("The broken state has no dialogue")?;
+    apologize(&bot, chat_id, get_update_topic(&update)).await?;
+    MyDialogue::new(storage, key)
+        .update(State::default())
+        .await?;
+    Ok(())
+}
+
+fn enter_dialogue() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
+    // The same as teloxide's dialogue::enter, but with our dialogue_key instead of the chat id
+    dptree::filter_map(|storage: MyStorage, update: Update| {
+        Some(MyDialogue::new(storage, get_dialogue_key(&update)?))
+    })
+    .filter_map_async(|dialogue: MyDialogue| async move {
+        match dialogue.get_or_default().await {
+            Ok(state) => Some(state),
+            Err(error) => {
+                log::error!("dialogue.get_or_default() failed: {:?}", error);
+                None
+            }
         }
-    }
+    })
 }
 
-pub fn schema() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
-    // Just a schema, nothing extraordinary
-    let normal_branch = dialogue::enter::<Update, ErasedStorage<State>, State, _>()
+pub fn schema<B>() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>>
+where
+    B: Requester<Err = RequestError> + Clone + Send + Sync + 'static,
+{
+    // Just a schema, nothing extraordinary. It's generic over the bot, so that the real bot
+    // gets `Bot`, and tests get a `TestBot`
+    let normal_branch = enter_dialogue()
         .branch(
             Update::filter_message()
                 .filter_command::<StartCommand>()
-                .branch(case![StartCommand::Start].endpoint(start)),
+                .branch(case![StartCommand::Start].endpoint(start::<B>)),
         )
         .branch(
             Update::filter_callback_query()
-                .branch(case![State::WhatDoYouWant].endpoint(what_is_the_first_number)),
+                .branch(case![State::WhatDoYouWant].endpoint(what_is_the_first_number::<B>)),
         )
         .branch(
             Update::filter_message()
                 .branch(
-                    case![State::GetFirstNumber { operation }].endpoint(what_is_the_second_number),
+                    case![State::GetFirstNumber { operation }]
+                        .endpoint(what_is_the_second_number::<B>),
                 )
                 .branch(
                     case![State::GetSecondNumber {
                         first_number,
                         operation
                     }]
-                    .endpoint(get_result),
+                    .endpoint(get_result::<B>),
                 ),
+        )
+        .branch(
+            // A button pressed when the bot doesn't wait for it, in any chat. The bot doesn't do
+            // anything, but it has to answer
+            Update::filter_callback_query().endpoint(ignore_button::<B>),
+        )
+        .branch(
+            // The other people in the group talk too, not only to the bot. If they didn't start
+            // the calculator, their messages are none of our business
+            dptree::filter(|update: Update| update.chat().is_some_and(|chat| !chat.is_private()))
+                .endpoint(ignore_update),
         );
 
-    // If the dialogue errors out - do not go further
+    // If the dialogue errors out - reset it, and do not go further
+    #[allow(clippy::let_and_return)]
     let catch_updated_dialogue_branch = dptree::entry()
-        .filter_async(check_if_the_state_is_ok)
+        .branch(dptree::filter_async(the_state_is_broken).endpoint(reset_the_broken_state::<B>))
         .branch(normal_branch);
 
     catch_updated_dialogue_branch
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failures::{Failure, FakeError};
+    use crate::operation::Operation;
+    use crate::test_utils::*;
+    use teloxide::ApiError;
+
+    #[tokio::test]
+    async fn test_a_broken_state_is_reset() {
+        let ctx = TestContext::new();
+        ctx.set_raw_state(&ctx.get_dialogue(), b"definitely not a state".to_vec());
+
+        let message = ctx.make_message("5", false, ChatType::Private);
+        ctx.dispatch(make_message_update(message)).await.unwrap();
+
+        // The user gets the apology, and starts from the beginning
+        ctx.check_the_state_and_text(State::Start, text::SORRY_BOT_UPDATED)
+            .await;
+        ctx.check_the_sent_methods(&["SendMessage"]);
+    }
+
+    #[tokio::test]
+    async fn test_a_broken_state_in_a_topic() {
+        // The apology goes into the topic, and only the dialogue in that topic is reset
+        let ctx = TestContext::new();
+        ctx.get_dialogue_in_topic(20)
+            .update(State::WhatDoYouWant)
+            .await
+            .unwrap();
+        ctx.set_raw_state(&ctx.get_dialogue_in_topic(10), vec![0xff]);
+
+        let message = ctx.make_topic_message("5", false, 10);
+        ctx.dispatch(make_message_update(message)).await.unwrap();
+
+        ctx.check_the_thread(Some(10));
+        assert_eq!(ctx.get_state_in_topic(10).await, State::Start);
+        assert_eq!(ctx.get_state_in_topic(20).await, State::WhatDoYouWant);
+    }
+
+    #[tokio::test]
+    async fn test_the_buttons_that_the_bot_doesnt_wait_for() {
+        // Someone who didn't start the calculator presses a button in a group, and then the owner
+        // presses their keyboard again, after they already chose. Both are answered, so the
+        // buttons don't keep loading, and nothing else happens
+        let alice = TestContext::new();
+        let bob = alice.as_user(alice.another_user().first_name("Bob"));
+        let start = alice.make_message("/start", true, ChatType::Group);
+        alice.dispatch(make_message_update(start)).await.unwrap();
+        let keyboard = alice.get_last_message();
+        let bob_call = bob.press_button(&keyboard, "Add");
+        bob.dispatch(make_callback_query_update(bob_call))
+            .await
+            .unwrap();
+        let state = State::GetFirstNumber {
+            operation: Operation::Add,
+        };
+        alice
+            .get_dialogue_in(ChatType::Group)
+            .update(state.clone())
+            .await
+            .unwrap();
+        let alice_call = alice.press_button(&keyboard, "Add");
+        alice
+            .dispatch(make_callback_query_update(alice_call))
+            .await
+            .unwrap();
+
+        alice.check_the_sent_methods(&[
+            "SendMessage",
+            "AnswerCallbackQuery",
+            "AnswerCallbackQuery",
+        ]);
+        let answers = alice.get_sent_requests_by_method("AnswerCallbackQuery");
+        assert_eq!(answers[0].json["text"], text::NOT_YOUR_KEYBOARD);
+        assert_eq!(answers[1].json.get("text"), None);
+        assert_eq!(bob.get_state_in(ChatType::Group).await, State::Start);
+        assert_eq!(alice.get_state_in(ChatType::Group).await, state);
+    }
+
+    #[tokio::test]
+    async fn test_the_apology_fails() {
+        // If the user blocked the bot, the error goes to the dispatcher, instead of a panic.
+        // The state stays broken, so the next time the user gets the apology again
+        let ctx = TestContext::new();
+        ctx.set_raw_state(&ctx.get_dialogue(), vec![0xff]);
+        ctx.fail(Failure::new(FakeError::bot_blocked()));
+
+        let message = ctx.make_message("5", false, ChatType::Private);
+        let result = ctx.try_dispatch(make_message_update(message)).await;
+
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RequestError>(),
+            Some(RequestError::Api(ApiError::BotBlocked))
+        ));
+        assert_eq!(ctx.get_raw_state(&ctx.get_dialogue()), Some(vec![0xff]));
+    }
+}
@@ -0,0 +1,1017 @@
+#![allow(dead_code)] // Not every builder is used in the tests, but they are there for you
+use chrono::{DateTime, Utc};
+use teloxide::types::*;
+
+use crate::identity::TestBotIdentity;
+pub use crate::intercept::TEST_DATE;
+use crate::intercept::{TEST_USER_FIRST_NAME, TEST_USER_LAST_NAME, TEST_USER_NAME};
+use crate::test_utils::ChatType;
+
+/*
+    Builders for the fake updates
+
+    Instead of writing the json by hand, we make the teloxide types directly, so the compiler
+    checks every field, and no text can break them (quotes, newlines, emojis, whatever).
+    If you need a message that the helpers in test_utils.rs don't make, build it here:
+
+    MessageBuilder::new(chat).from(user).text("Hi").reply_to(other_message).build()
+*/
+
+pub fn test_date() -> DateTime<Utc> {
+    DateTime::from_timestamp(TEST_DATE, 0).unwrap()
+}
+
+pub fn make_user(user_id: i64) -> User {
+    // The test user, but with any id you want
+    FakeUser::new(user_id).build()
+}
+
+pub fn make_bot_user() -> User {
+    // The same bot as in make_bot_string(), but as a `User`, like in the `from` of its messages
+    TestBotIdentity::default().user()
+}
+
+pub fn make_chat(chat_id: i64, chat_type: ChatType) -> Chat {
+    FakeChat::new(chat_id, chat_type).build()
+}
+
+/*
+    Fake users and chats
+
+    The helpers above always make the same "Test User" in the same "Test Group". If a test needs
+    someone else, like a second user in the same group, an admin, or a user with another language,
+    describe them with these, and give them to the MessageBuilder, to `TestContext::as_user`, or,
+    for the chats, to any `make_*` helper of the TestContext instead of a ChatType:
+
+    let admin = FakeUser::new(42).first_name("Admin").language("uk").premium(true);
+    MessageBuilder::new(FakeChat::new(-42, ChatType::Group)).from(admin).text("Hi").build()
+
+    The fake bot is in identity.rs, it's the `TestBotIdentity` (or `FakeBot`, the same thing)
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FakeUser {
+    pub id: i64,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+    pub language_code: Option<String>, // Like "en", telegram sends what the user's app is set to
+    pub is_premium: bool,
+    pub is_bot: bool,
+}
+
+impl FakeUser {
+    pub fn new(id: i64) -> Self {
+        // The test user, change whatever you need with the methods below
+        Self {
+            id,
+            first_name: TEST_USER_FIRST_NAME.to_string(),
+            last_name: Some(TEST_USER_LAST_NAME.to_string()),
+            username: Some(TEST_USER_NAME.to_string()),
+            language_code: Some("en".to_string()),
+            is_premium: false,
+            is_bot: false,
+        }
+    }
+
+    pub fn first_name(mut self, first_name: impl Into<String>) -> Self {
+        self.first_name = first_name.into();
+        self
+    }
+
+    pub fn last_name(mut self, last_name: Option<&str>) -> Self {
+        // None, if the user didn't set it
+        self.last_name = last_name.map(str::to_string);
+        self
+    }
+
+    pub fn username(mut self, username: Option<&str>) -> Self {
+        self.username = username.map(str::to_string);
+        self
+    }
+
+    pub fn language(mut self, language_code: Option<&str>) -> Self {
+        // Telegram doesn't always know it, so handlers should work without it too
+        self.language_code = language_code.map(str::to_string);
+        self
+    }
+
+    pub fn premium(mut self, is_premium: bool) -> Self {
+        self.is_premium = is_premium;
+        self
+    }
+
+    pub fn bot(mut self, is_bot: bool) -> Self {
+        // Other bots in the group, not the one we test (that one is the TestBotIdentity)
+        self.is_bot = is_bot;
+        self
+    }
+
+    pub fn build(self) -> User {
+        User {
+            id: UserId(self.id as u64),
+            is_bot: self.is_bot,
+            first_name: self.first_name,
+            last_name: self.last_name,
+            username: self.username,
+            language_code: self.language_code,
+            is_premium: self.is_premium,
+            added_to_attachment_menu: false,
+        }
+    }
+}
+
+impl From<FakeUser> for User {
+    // So that FakeUser can be given to anything that takes a User, like MessageBuilder::from
+    fn from(user: FakeUser) -> Self {
+        user.build()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FakeChat {
+    pub id: i64,
+    pub chat_type: ChatType,
+    pub title: Option<String>, // None is "Test Group" or "Test Channel", private chats don't have it
+    pub username: Option<String>,
+    pub first_name: Option<String>, // The names are only for the private chats, from the user
+    pub last_name: Option<String>,
+    pub is_forum: bool, // Only supergroups can be forums, with topics (message threads)
+}
+
+impl FakeChat {
+    pub fn new(id: i64, chat_type: ChatType) -> Self {
+        // The private chats are with the test user, the rest are the test group or channel
+        let private = chat_type == ChatType::Private;
+        Self {
+            id,
+            chat_type,
+            title: None,
+            username: private.then(|| TEST_USER_NAME.to_string()),
+            first_name: private.then(|| TEST_USER_FIRST_NAME.to_string()),
+            last_name: private.then(|| TEST_USER_LAST_NAME.to_string()),
+            is_forum: false,
+        }
+    }
+
+    pub fn private(user: &FakeUser) -> Self {
+        // The private chat with this user, it has the same id and the names as the user
+        Self {
+            id: user.id,
+            chat_type: ChatType::Private,
+            title: None,
+            username: user.username.clone(),
+            first_name: Some(user.first_name.clone()),
+            last_name: user.last_name.clone(),
+            is_forum: false,
+        }
+    }
+
+    pub fn chat_type(mut self, chat_type: ChatType) -> Self {
+        self.chat_type = chat_type;
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn forum(mut self, is_forum: bool) -> Self {
+        // A supergroup with topics, the messages in them have a message_thread_id
+        self.is_forum = is_forum;
+        self
+    }
+
+    pub fn username(mut self, username: Option<&str>) -> Self {
+        // Public groups and channels have it too, it's their @link
+        self.username = username.map(str::to_string);
+        self
+    }
+
+    pub fn build(self) -> Chat {
+        let title = |default: &str| self.title.clone().unwrap_or(default.to_string());
+        let kind = match self.chat_type {
+            ChatType::Private => ChatKind::Private(ChatPrivate {
+                username: self.username.clone(),
+                first_name: self.first_name.clone(),
+                last_name: self.last_name.clone(),
+                emoji_status_custom_emoji_id: None,
+                bio: None,
+                has_private_forwards: None,
+                has_restricted_voice_and_video_messages: None,
+            }),
+            ChatType::Group => make_public_chat_kind(
+                &title("Test Group"),
+                PublicChatKind::Group(PublicChatGroup { permissions: None }),
+            ),
+            // Telegram doesn't have a separate type for gigagroups, they are supergroups too
+            ChatType::Supergroup | ChatType::Gigagroup => make_public_chat_kind(
+                &title("Test Group"),
+                PublicChatKind::Supergroup(PublicChatSupergroup {
+                    username: self.username.clone(),
+                    active_usernames: None,
+                    is_forum: self.is_forum,
+                    sticker_set_name: None,
+                    can_set_sticker_set: None,
+                    permissions: None,
+                    slow_mode_delay: None,
+                    linked_chat_id: None,
+                    location: None,
+                    join_to_send_messages: None,
+                    join_by_request: None,
+                }),
+            ),
+            ChatType::Channel => make_public_chat_kind(
+                &title("Test Channel"),
+                PublicChatKind::Channel(PublicChatChannel {
+                    username: self.username.clone(),
+                    linked_chat_id: None,
+                }),
+            ),
+        };
+        Chat {
+            id: ChatId(self.id),
+            kind,
+            photo: None,
+            pinned_message: None,
+            message_auto_delete_time: None,
+            has_hidden_members: false,
+            has_aggressive_anti_spam_enabled: false,
+        }
+    }
+}
+
+impl From<FakeChat> for Chat {
+    fn from(chat: FakeChat) -> Self {
+        chat.build()
+    }
+}
+
+fn make_public_chat_kind(title: &str, kind: PublicChatKind) -> ChatKind {
+    ChatKind::Public(ChatPublic {
+        title: Some(title.to_string()),
+        kind,
+        description: None,
+        invite_link: None,
+        has_protected_content: None,
+    })
+}
+
+pub fn make_photo_sizes() -> Vec<PhotoSize> {
+    // Telegram sends every photo in a few sizes, from the smallest to the biggest
+    [
+        (932, 90, 56),
+        (13483, 320, 200),
+        (60882, 800, 500),
+        (116270, 1280, 800),
+    ]
+    .into_iter()
+    .map(|(size, width, height)| PhotoSize {
+        file: FileMeta {
+            id: "1234567890".to_string(),
+            unique_id: "1234567890".to_string(),
+            size,
+        },
+        width,
+        height,
+    })
+    .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    id: i32,
+    thread_id: Option<i32>,
+    date: DateTime<Utc>,
+    chat: Chat,
+    from: Option<User>, // Messages in channels don't have it
+    forward: Option<Forward>,
+    edit_date: Option<DateTime<Utc>>, // Only the edited messages have it
+    reply_to: Option<Message>,
+    media: MediaKind, // What is in the message: text, photo, etc
+    reply_markup: Option<InlineKeyboardMarkup>,
+    is_topic_message: bool,
+    special_kind: Option<MessageKind>, // For the messages that aren't normal ones, like dice
+}
+
+impl MessageBuilder {
+    pub fn new(chat: impl Into<Chat>) -> Self {
+        // An empty text message with id 1, set everything else you need with the methods below
+        Self {
+            id: 1,
+            thread_id: None,
+            date: test_date(),
+            chat: chat.into(),
+            from: None,
+            forward: None,
+            edit_date: None,
+            reply_to: None,
+            media: MediaKind::Text(MediaText {
+                text: String::new(),
+                entities: vec![],
+            }),
+            reply_markup: None,
+            is_topic_message: false,
+            special_kind: None,
+        }
+    }
+
+    pub fn id(mut self, id: i32) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn thread_id(mut self, thread_id: i32) -> Self {
+        self.thread_id = Some(thread_id);
+        self
+    }
+
+    pub fn topic_message(mut self, is_topic_message: bool) -> Self {
+        // If the message was sent into a forum topic, the thread id is the id of the topic
+        self.is_topic_message = is_topic_message;
+        self
+    }
+
+    pub fn date(mut self, date: DateTime<Utc>) -> Self {
+        self.date = date;
+        self
+    }
+
+    pub fn chat(mut self, chat: impl Into<Chat>) -> Self {
+        self.chat = chat.into();
+        self
+    }
+
+    pub fn from(mut self, user: impl Into<User>) -> Self {
+        self.from = Some(user.into());
+        self
+    }
+
+    pub fn reply_to(mut self, message: Message) -> Self {
+        self.reply_to = Some(message);
+        self
+    }
+
+    pub fn edit_date(mut self, edit_date: DateTime<Utc>) -> Self {
+        // For the EditedMessage updates, telegram sends the whole message again, with this date
+        self.edit_date = Some(edit_date);
+        self
+    }
+
+    pub fn forward(mut self, forward: Forward) -> Self {
+        self.forward = Some(forward);
+        self
+    }
+
+    pub fn forwarded_from(self, from: ForwardedFrom) -> Self {
+        // If you don't care about the rest of the forward info
+        let date = self.date;
+        self.forward(Forward {
+            date,
+            from,
+            signature: None,
+            message_id: None,
+        })
+    }
+
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.media = MediaKind::Text(MediaText {
+            text: text.into(),
+            entities: vec![],
+        });
+        self
+    }
+
+    pub fn command(self, text: impl Into<String>) -> Self {
+        // A text that starts with a command, like "/start" or "/start something"
+        let text: String = text.into();
+        let command = text.split_whitespace().next().unwrap_or_default();
+        // Telegram counts the offsets and lengths in UTF-16, not in bytes
+        let length = command.encode_utf16().count();
+        self.text(text)
+            .entity(MessageEntity::new(MessageEntityKind::BotCommand, 0, length))
+    }
+
+    pub fn entities(mut self, entities: Vec<MessageEntity>) -> Self {
+        // Entities of the text, or of the caption, if the message has media
+        match &mut self.media {
+            MediaKind::Text(media) => media.entities = entities,
+            media => {
+                if let Some((_, caption_entities)) = caption_mut(media) {
+                    *caption_entities = entities;
+                }
+            }
+        }
+        self
+    }
+
+    pub fn entity(mut self, entity: MessageEntity) -> Self {
+        match &mut self.media {
+            MediaKind::Text(media) => media.entities.push(entity),
+            media => {
+                if let Some((_, caption_entities)) = caption_mut(media) {
+                    caption_entities.push(entity);
+                }
+            }
+        }
+        self
+    }
+
+    pub fn media(mut self, media: MediaKind) -> Self {
+        // Any media you want, if there is no shortcut for it here
+        self.media = media;
+        self
+    }
+
+    pub fn photo(self, photo: Vec<PhotoSize>) -> Self {
+        self.media(MediaKind::Photo(MediaPhoto {
+            photo,
+            caption: None,
+            caption_entities: vec![],
+            has_media_spoiler: false,
+            media_group_id: None,
+        }))
+    }
+
+    /*
+        The media that users send. The files are fake, but they look like the real ones, if you
+        need something specific, change it with `.media()`
+    */
+
+    pub fn document(self) -> Self {
+        self.media(MediaKind::Document(MediaDocument {
+            document: Document {
+                file: make_file_meta("document", 10240),
+                thumb: None,
+                file_name: Some("document.pdf".to_string()),
+                mime_type: Some(mime::APPLICATION_PDF),
+            },
+            caption: None,
+            caption_entities: vec![],
+            media_group_id: None,
+        }))
+    }
+
+    pub fn video(self) -> Self {
+        self.media(MediaKind::Video(MediaVideo {
+            video: Video {
+                file: make_file_meta("video", 1048576),
+                width: 1280,
+                height: 720,
+                duration: 10,
+                thumb: None,
+                file_name: Some("video.mp4".to_string()),
+                mime_type: Some("video/mp4".parse().unwrap()),
+            },
+            caption: None,
+            caption_entities: vec![],
+            has_media_spoiler: false,
+            media_group_id: None,
+        }))
+    }
+
+    pub fn animation(self) -> Self {
+        // Gifs, telegram converts them into mp4 videos without sound
+        self.media(MediaKind::Animation(MediaAnimation {
+            animation: Animation {
+                file: make_file_meta("animation", 65536),
+                width: 320,
+                height: 240,
+                duration: 3,
+                thumb: None,
+                file_name: Some("animation.mp4".to_string()),
+                mime_type: Some("video/mp4".parse().unwrap()),
+            },
+            caption: None,
+            caption_entities: vec![],
+            has_media_spoiler: false,
+        }))
+    }
+
+    pub fn audio(self) -> Self {
+        self.media(MediaKind::Audio(MediaAudio {
+            audio: Audio {
+                file: make_file_meta("audio", 4194304),
+                duration: 180,
+                performer: Some("Test Performer".to_string()),
+                title: Some("Test Song".to_string()),
+                file_name: Some("song.mp3".to_string()),
+                mime_type: Some("audio/mpeg".parse().unwrap()),
+                thumb: None,
+            },
+            caption: None,
+            caption_entities: vec![],
+            media_group_id: None,
+        }))
+    }
+
+    pub fn voice(self) -> Self {
+        self.media(MediaKind::Voice(MediaVoice {
+            voice: Voice {
+                file: make_file_meta("voice", 8192),
+                duration: 5,
+                mime_type: Some("audio/ogg".parse().unwrap()),
+            },
+            caption: None,
+            caption_entities: vec![],
+        }))
+    }
+
+    pub fn video_note(self) -> Self {
+        // The round videos
+        self.media(MediaKind::VideoNote(MediaVideoNote {
+            video_note: VideoNote {
+                file: make_file_meta("video_note", 262144),
+                length: 240,
+                duration: 5,
+                thumb: None,
+            },
+        }))
+    }
+
+    pub fn sticker(self) -> Self {
+        self.media(MediaKind::Sticker(MediaSticker {
+            sticker: Sticker {
+                file: make_file_meta("sticker", 16384),
+                width: 512,
+                height: 512,
+                kind: StickerKind::Regular {
+                    premium_animation: None,
+                },
+                format: StickerFormat::Raster,
+                thumb: None,
+                emoji: Some("👍".to_string()),
+                set_name: Some("test_stickers".to_string()),
+            },
+        }))
+    }
+
+    pub fn location(self, latitude: f64, longitude: f64) -> Self {
+        self.media(MediaKind::Location(MediaLocation {
+            location: make_location(latitude, longitude),
+        }))
+    }
+
+    pub fn venue(self, latitude: f64, longitude: f64, title: &str, address: &str) -> Self {
+        self.media(MediaKind::Venue(MediaVenue {
+            venue: Venue {
+                location: make_location(latitude, longitude),
+                title: title.to_string(),
+                address: address.to_string(),
+                foursquare_id: None,
+                foursquare_type: None,
+                google_place_id: None,
+                google_place_type: None,
+            },
+        }))
+    }
+
+    pub fn contact(self, phone_number: &str, first_name: &str) -> Self {
+        self.media(MediaKind::Contact(MediaContact {
+            contact: Contact {
+                phone_number: phone_number.to_string(),
+                first_name: first_name.to_string(),
+                last_name: None,
+                user_id: None,
+                vcard: None,
+            },
+        }))
+    }
+
+    pub fn poll(self, question: &str, options: &[&str]) -> Self {
+        self.media(MediaKind::Poll(MediaPoll {
+            poll: make_poll(question, options),
+        }))
+    }
+
+    pub fn caption(mut self, text: impl Into<String>) -> Self {
+        // Set the media first, text messages and stickers don't have captions
+        let (caption, _) =
+            caption_mut(&mut self.media).expect("This kind of message can't have a caption");
+        *caption = Some(text.into());
+        self
+    }
+
+    pub fn media_group_id(mut self, media_group_id: impl Into<String>) -> Self {
+        let media_group_id = Some(media_group_id.into());
+        match &mut self.media {
+            MediaKind::Audio(media) => media.media_group_id = media_group_id,
+            MediaKind::Document(media) => media.media_group_id = media_group_id,
+            MediaKind::Photo(media) => media.media_group_id = media_group_id,
+            MediaKind::Video(media) => media.media_group_id = media_group_id,
+            _ => panic!("This kind of message can't be in a media group"),
+        }
+        self
+    }
+
+    pub fn reply_markup(mut self, reply_markup: InlineKeyboardMarkup) -> Self {
+        self.reply_markup = Some(reply_markup);
+        self
+    }
+
+    pub fn web_app_data(mut self, data: impl Into<String>, button_text: impl Into<String>) -> Self {
+        // It's not a normal message, it's a service one
+        self.special_kind = Some(MessageKind::WebAppData(MessageWebAppData {
+            web_app_data: WebAppData {
+                data: data.into(),
+                button_text: button_text.into(),
+            },
+        }));
+        self
+    }
+
+    pub fn dice(mut self, emoji: DiceEmoji, value: i32) -> Self {
+        // Dice aren't media in teloxide, they are their own kind of message
+        self.special_kind = Some(MessageKind::Dice(MessageDice {
+            dice: Dice { emoji, value },
+        }));
+        self
+    }
+
+    pub fn build(self) -> Message {
+        let kind = match self.special_kind {
+            Some(kind) => kind,
+            None => MessageKind::Common(MessageCommon {
+                from: self.from,
+                sender_chat: None,
+                author_signature: None,
+                forward: self.forward,
+                reply_to_message: self.reply_to.map(Box::new),
+                edit_date: self.edit_date,
+                media_kind: self.media,
+                reply_markup: self.reply_markup,
+                is_topic_message: self.is_topic_message,
+                is_automatic_forward: false,
+                has_protected_content: false,
+            }),
+        };
+        Message {
+            id: MessageId(self.id),
+            thread_id: self.thread_id,
+            date: self.date,
+            chat: self.chat,
+            via_bot: None,
+            kind,
+        }
+    }
+}
+
+pub fn make_poll(question: &str, options: &[&str]) -> Poll {
+    // A new regular anonymous poll, that nobody voted in yet
+    Poll {
+        id: "1".to_string(),
+        question: question.to_string(),
+        options: options
+            .iter()
+            .map(|option| PollOption {
+                text: option.to_string(),
+                voter_count: 0,
+            })
+            .collect(),
+        is_closed: false,
+        total_voter_count: 0,
+        is_anonymous: true,
+        poll_type: PollType::Regular,
+        allows_multiple_answers: false,
+        correct_option_id: None,
+        explanation: None,
+        explanation_entities: None,
+        open_period: None,
+        close_date: None,
+    }
+}
+
+pub fn make_shipping_address() -> ShippingAddress {
+    ShippingAddress {
+        country_code: CountryCode::US,
+        state: "California".to_string(),
+        city: "Test City".to_string(),
+        street_line1: "Test Street, 1".to_string(),
+        street_line2: String::new(),
+        post_code: "12345".to_string(),
+    }
+}
+
+pub fn make_media_group(messages: Vec<MessageBuilder>) -> Vec<Message> {
+    // Telegram sends every photo (or video, document, audio) of an album as a separate message,
+    // with the same media_group_id and the ids one after another, starting from the first one.
+    // Your bot gets them as separate updates, so run them one by one
+    let first_id = messages.first().map_or(1, |message| message.id);
+    messages
+        .into_iter()
+        .zip(first_id..)
+        .map(|(message, id)| message.id(id).media_group_id("1").build())
+        .collect()
+}
+
+fn make_file_meta(name: &str, size: u32) -> FileMeta {
+    FileMeta {
+        id: format!("{name}_file_id"),
+        unique_id: format!("{name}_file_unique_id"),
+        size,
+    }
+}
+
+fn make_location(latitude: f64, longitude: f64) -> Location {
+    Location {
+        longitude,
+        latitude,
+        horizontal_accuracy: None,
+        live_period: None,
+        heading: None,
+        proximity_alert_radius: None,
+    }
+}
+
+fn caption_mut(media: &mut MediaKind) -> Option<(&mut Option<String>, &mut Vec<MessageEntity>)> {
+    // The caption and its entities, for every media that has them
+    match media {
+        MediaKind::Animation(media) => Some((&mut media.caption, &mut media.caption_entities)),
+        MediaKind::Audio(media) => Some((&mut media.caption, &mut media.caption_entities)),
+        MediaKind::Document(media) => Some((&mut media.caption, &mut media.caption_entities)),
+        MediaKind::Photo(media) => Some((&mut media.caption, &mut media.caption_entities)),
+        MediaKind::Video(media) => Some((&mut media.caption, &mut media.caption_entities)),
+        MediaKind::Voice(media) => Some((&mut media.caption, &mut media.caption_entities)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_bot::TestBot;
+    use crate::test_utils::*;
+    use crate::State;
+    use std::sync::{Arc, Mutex};
+    use teloxide::{adaptors::DefaultParseMode, dispatching::UpdateFilterExt, prelude::*};
+
+    #[test]
+    fn test_text_that_would_break_json() {
+        // Quotes, backslashes, newlines and emojis, all of that broke the old format! strings
+        let text = "/start \"quoted\" \\ back\nslash 👍";
+        let message = MessageBuilder::new(make_chat(TEST_USER_ID, ChatType::Private))
+            .from(make_user(TEST_USER_ID))
+            .command(text)
+            .build();
+
+        assert_eq!(message.text(), Some(text));
+        let entity = &message.entities().unwrap()[0];
+        assert_eq!(entity.kind, MessageEntityKind::BotCommand);
+        assert_eq!(entity.length, "/start".len());
+        // And it has to look exactly like the one telegram would've sent
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), message);
+    }
+
+    #[test]
+    fn test_every_media_looks_like_from_telegram() {
+        // teloxide guesses the kind of the message by its fields, so if some builder misses a
+        // field, the message would turn into a different kind (or not parse at all) in telegram
+        let builder = MessageBuilder::new(make_chat(TEST_USER_ID, ChatType::Private))
+            .from(make_user(TEST_USER_ID));
+        let messages = [
+            builder.clone().document().caption("A document"),
+            builder.clone().video(),
+            builder.clone().animation(),
+            builder.clone().audio(),
+            builder.clone().voice(),
+            builder.clone().video_note(),
+            builder.clone().sticker(),
+            builder.clone().location(50.45, 30.52),
+            builder
+                .clone()
+                .venue(50.45, 30.52, "Test Venue", "Test Street, 1"),
+            builder.clone().contact("+1234567890", "Test"),
+            builder.clone().dice(DiceEmoji::Dice, 6),
+            builder.clone().poll("Test?", &["Yes", "No"]),
+        ];
+        for message in messages {
+            let message = message.build();
+            let json = serde_json::to_string(&message).unwrap();
+            assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_media_group() {
+        let builder = MessageBuilder::new(make_chat(TEST_USER_ID, ChatType::Private)).id(10);
+        let album = make_media_group(vec![
+            builder
+                .clone()
+                .photo(make_photo_sizes())
+                .caption("An album"),
+            builder.clone().video(),
+            builder.photo(make_photo_sizes()),
+        ]);
+
+        let ids: Vec<i32> = album.iter().map(|message| message.id.0).collect();
+        assert_eq!(ids, [10, 11, 12]);
+        assert!(album
+            .iter()
+            .all(|message| message.media_group_id() == Some("1")));
+        assert_eq!(album[0].caption(), Some("An album"));
+    }
+
+    #[test]
+    fn test_every_update_looks_like_from_telegram() {
+        let ctx = TestContext::new();
+        let updates = [
+            make_edited_message_update(ctx.make_edited_message("Edited", ChatType::Private)),
+            make_channel_post_update(ctx.make_channel_post("Post")),
+            make_inline_query_update(ctx.make_inline_query("query")),
+            make_chosen_inline_result_update(ctx.make_chosen_inline_result("1", "query")),
+            make_shipping_query_update(ctx.make_shipping_query("payload")),
+            make_pre_checkout_query_update(ctx.make_pre_checkout_query("payload", 100)),
+            make_poll_update(make_poll("Test?", &["Yes", "No"])),
+            make_poll_answer_update(ctx.make_poll_answer(vec![0])),
+            make_my_chat_member_update(ctx.make_my_chat_member(
+                ChatType::Group,
+                ChatMemberKind::Left,
+                ChatMemberKind::Member,
+            )),
+            make_chat_member_update(ctx.make_chat_member(
+                ChatType::Supergroup,
+                ChatMemberKind::Member,
+                ChatMemberKind::Left,
+            )),
+            make_chat_join_request_update(ctx.make_chat_join_request(ChatType::Supergroup)),
+        ];
+        for update in updates {
+            let json = serde_json::to_string(&update).unwrap();
+            let parsed: Update = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, update);
+            // The updates that have a user, are all from the test user
+            if let Some(user) = update.user() {
+                assert_eq!(user.id, UserId(ctx.user_id as u64));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_every_update_goes_through_a_schema() {
+        // The calculator handles only the messages and the buttons, so here is a schema with a
+        // branch for every other kind of update, and every branch writes down that it got one
+        let handled: Arc<Mutex<Vec<&str>>> = Arc::default();
+        let handled_as = |kind: &'static str| -> Schema {
+            let handled = handled.clone();
+            dptree::endpoint(move || {
+                let handled = handled.clone();
+                async move {
+                    handled.lock().unwrap().push(kind);
+                    Ok(())
+                }
+            })
+        };
+        let answer_inline_query = |bot: DefaultParseMode<TestBot>, query: InlineQuery| async move {
+            // A real answer, to see that the requests from these branches are intercepted too
+            bot.answer_inline_query(query.id, []).await?;
+            Ok(())
+        };
+        let schema = dptree::entry()
+            .branch(Update::filter_edited_message().chain(handled_as("EditedMessage")))
+            .branch(Update::filter_channel_post().chain(handled_as("ChannelPost")))
+            .branch(Update::filter_inline_query().endpoint(answer_inline_query))
+            .branch(Update::filter_chosen_inline_result().chain(handled_as("ChosenInlineResult")))
+            .branch(Update::filter_shipping_query().chain(handled_as("ShippingQuery")))
+            .branch(Update::filter_pre_checkout_query().chain(handled_as("PreCheckoutQuery")))
+            .branch(Update::filter_poll().chain(handled_as("Poll")))
+            .branch(Update::filter_poll_answer().chain(handled_as("PollAnswer")))
+            .branch(Update::filter_my_chat_member().chain(handled_as("MyChatMember")))
+            .branch(Update::filter_chat_member().chain(handled_as("ChatMember")))
+            .branch(Update::filter_chat_join_request().chain(handled_as("ChatJoinRequest")));
+        let ctx = TestContext::new().with_schema(schema);
+
+        let updates = [
+            make_edited_message_update(ctx.make_edited_message("Edited", ChatType::Private)),
+            make_channel_post_update(ctx.make_channel_post("Post")),
+            make_inline_query_update(ctx.make_inline_query("query")),
+            make_chosen_inline_result_update(ctx.make_chosen_inline_result("1", "query")),
+            make_shipping_query_update(ctx.make_shipping_query("payload")),
+            make_pre_checkout_query_update(ctx.make_pre_checkout_query("payload", 100)),
+            make_poll_update(make_poll("Test?", &["Yes", "No"])),
+            make_poll_answer_update(ctx.make_poll_answer(vec![0])),
+            make_my_chat_member_update(ctx.make_my_chat_member(
+                ChatType::Group,
+                ChatMemberKind::Left,
+                ChatMemberKind::Member,
+            )),
+            make_chat_member_update(ctx.make_chat_member(
+                ChatType::Supergroup,
+                ChatMemberKind::Member,
+                ChatMemberKind::Left,
+            )),
+            make_chat_join_request_update(ctx.make_chat_join_request(ChatType::Supergroup)),
+        ];
+        for update in updates {
+            ctx.dispatch(update).await.unwrap();
+        }
+
+        assert_eq!(
+            *handled.lock().unwrap(),
+            [
+                "EditedMessage",
+                "ChannelPost",
+                "ChosenInlineResult",
+                "ShippingQuery",
+                "PreCheckoutQuery",
+                "Poll",
+                "PollAnswer",
+                "MyChatMember",
+                "ChatMember",
+                "ChatJoinRequest"
+            ]
+        );
+        ctx.check_the_sent_methods(&["AnswerInlineQuery"]);
+    }
+
+    #[tokio::test]
+    async fn test_run_an_update_without_a_chat() {
+        // There is no dialogue for an inline query, so the state isn't saved anywhere
+        let answer_inline_query = |bot: DefaultParseMode<TestBot>, query: InlineQuery| async move {
+            bot.answer_inline_query(query.id, []).await?;
+            Ok(())
+        };
+        let ctx = TestContext::new()
+            .with_schema(Update::filter_inline_query().endpoint(answer_inline_query));
+        let update = make_inline_query_update(ctx.make_inline_query("2 + 3"));
+
+        let dialogue = ctx.run_update(update, State::WhatDoYouWant).await.unwrap();
+        assert!(dialogue.is_none());
+        ctx.check_the_sent_methods(&["AnswerInlineQuery"]);
+        assert_eq!(ctx.get_state().await, State::Start);
+    }
+
+    #[tokio::test]
+    async fn test_the_helpers_in_another_chat() {
+        // Every make_* helper takes a FakeChat too, not only the test's own chats
+        let ctx = TestContext::new();
+        let other_group = FakeChat::new(-1000000000042, ChatType::Group).title("Other group");
+        let message = ctx.make_message("/start", true, &other_group);
+        assert_eq!(message.chat, other_group.clone().build());
+        assert_eq!(message.chat.title(), Some("Other group"));
+        let call = ctx.make_callback_query("add", &other_group);
+        assert_eq!(call.message.unwrap().chat.id, ChatId(-1000000000042));
+
+        // And the dialogue in it is separate from the test's own group
+        ctx.dispatch(make_message_update(message)).await.unwrap();
+        assert_eq!(ctx.get_state_in(&other_group).await, State::WhatDoYouWant);
+        assert_eq!(ctx.get_state_in(ChatType::Group).await, State::Start);
+    }
+
+    #[tokio::test]
+    async fn test_two_users_in_the_same_group() {
+        let ctx = TestContext::new();
+        let admin = ctx.as_user(
+            ctx.another_user()
+                .first_name("Admin")
+                .username(None)
+                .language(Some("uk"))
+                .premium(true),
+        );
+        let first = ctx.make_message("Hi", false, ChatType::Group);
+        let second = admin.make_message("Hello", false, ChatType::Group);
+
+        assert_eq!(first.chat.id, second.chat.id);
+        assert_ne!(first.from().unwrap().id, second.from().unwrap().id);
+        let from = second.from().unwrap();
+        assert_eq!(from.first_name, "Admin");
+        assert_eq!(from.username, None);
+        assert_eq!(from.language_code.as_deref(), Some("uk"));
+        assert!(from.is_premium);
+        // The private chat with the admin has their names, not the test user's
+        let private = admin.make_chat(ChatType::Private);
+        assert_eq!(private.id, ChatId(admin.user_id));
+        assert_eq!(private.first_name(), Some("Admin"));
+
+        // Both of them talk to the same bot
+        let update = make_message_update(admin.make_message("/start", true, ChatType::Private));
+        admin.dispatch(update).await.unwrap();
+        assert_eq!(ctx.get_sent_requests().len(), 1);
+    }
+
+    #[test]
+    fn test_reply_with_caption() {
+        let chat = make_chat(TEST_USER_ID, ChatType::Supergroup);
+        let original = MessageBuilder::new(chat.clone())
+            .from(make_bot_user())
+            .text("Send me a photo")
+            .build();
+        let message = MessageBuilder::new(chat)
+            .id(2)
+            .from(make_user(TEST_USER_ID))
+            .photo(make_photo_sizes())
+            .caption("Here it is")
+            .entity(MessageEntity::bold(0, 4))
+            .reply_to(original.clone())
+            .forwarded_from(ForwardedFrom::SenderName("Someone".to_string()))
+            .build();
+
+        assert_eq!(message.caption(), Some("Here it is"));
+        assert_eq!(message.caption_entities().unwrap().len(), 1);
+        assert_eq!(message.reply_to_message(), Some(&original));
+        assert!(message.chat.is_supergroup());
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), message);
+    }
+}
@@ -0,0 +1,169 @@
+#![allow(dead_code)]
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use teloxide::{
+    types::{ChatId, Recipient},
+    ApiError, RequestError,
+};
+
+use crate::intercept::SentRequest;
+
+/*
+    Making the intercepted requests fail on purpose
+
+    Telegram fails sometimes: users block the bot, it hits the flood limit, the network goes down.
+    To test what the handlers do then, a test can tell the TestBot to fail some requests:
+
+    ctx.fail(Failure::new(FakeError::bot_blocked()).method("SendMessage").times(1));
+
+    The next matching request returns that error instead of the fake response. The request is still
+    in the journal (the bot did try to send it), but the history of the chat doesn't change.
+*/
+
+pub type Failures = Arc<Mutex<Vec<Failure>>>;
+// Shared between the clones of the bot, the same way as the Journal in intercept.rs
+
+#[derive(Clone, Debug)]
+pub enum FakeError {
+    // RequestError can't be cloned, so we keep what error to make, and make a new one every time
+    Api(ApiError),
+    RetryAfter(Duration),
+    MigrateToChatId(i64),
+    Network,
+}
+
+impl FakeError {
+    // The errors that you'll most likely need
+    pub fn bot_blocked() -> Self {
+        FakeError::Api(ApiError::BotBlocked)
+    }
+
+    pub fn message_not_modified() -> Self {
+        FakeError::Api(ApiError::MessageNotModified)
+    }
+
+    pub fn retry_after(seconds: u64) -> Self {
+        FakeError::RetryAfter(Duration::from_secs(seconds))
+    }
+
+    pub fn make(&self) -> RequestError {
+        match self {
+            FakeError::Api(error) => RequestError::Api(error.clone()),
+            FakeError::RetryAfter(duration) => RequestError::RetryAfter(*duration),
+            FakeError::MigrateToChatId(chat_id) => RequestError::MigrateToChatId(*chat_id),
+            FakeError::Network => RequestError::Network(make_network_error()),
+        }
+    }
+}
+
+fn make_network_error() -> reqwest::Error {
+    // reqwest doesn't let us make its errors directly, so we make a request that can't be sent.
+    // The text of the error is different from a real network error, but the type is the same
+    reqwest::Client::new()
+        .get("telegram is down")
+        .build()
+        .unwrap_err()
+}
+
+#[derive(Clone, Debug)]
+pub struct Failure {
+    error: FakeError,
+    method: Option<String>, // Only the requests of this type, e.g. "SendMessage"
+    chat_id: Option<ChatId>, // Only the requests to this chat
+    times: Option<usize>,   // How many requests fail, None is all of them
+}
+
+impl Failure {
+    pub fn new(error: FakeError) -> Self {
+        // Fails every request, until you add some limits to it
+        Self {
+            error,
+            method: None,
+            chat_id: None,
+            times: None,
+        }
+    }
+
+    pub fn method(mut self, method: &str) -> Self {
+        self.method = Some(method.to_string());
+        self
+    }
+
+    pub fn chat(mut self, chat_id: ChatId) -> Self {
+        self.chat_id = Some(chat_id);
+        self
+    }
+
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    fn matches(&self, request: &SentRequest) -> bool {
+        if self.times == Some(0) {
+            return false;
+        }
+        let method_matches = self
+            .method
+            .as_ref()
+            .is_none_or(|method| method == request.method);
+        let chat_matches = self
+            .chat_id
+            .is_none_or(|chat_id| request.chat_id == Some(Recipient::Id(chat_id)));
+        method_matches && chat_matches
+    }
+}
+
+pub fn take_failure(failures: &Failures, request: &SentRequest) -> Option<RequestError> {
+    // The first failure that matches the request, if there is one. It's used up, so with
+    // `.times(2)` only the next two matching requests fail
+    let mut failures = failures.lock().unwrap();
+    let index = failures
+        .iter()
+        .position(|failure| failure.matches(request))?;
+    let error = failures[index].error.make();
+    if let Some(times) = &mut failures[index].times {
+        *times -= 1;
+        if *times == 0 {
+            failures.remove(index);
+        }
+    }
+    Some(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use teloxide::prelude::*;
+
+    #[tokio::test]
+    async fn test_failures() {
+        let ctx = TestContext::new();
+        let bot = ctx.bot.clone();
+        let user = ChatId(ctx.user_id);
+        let group = ChatId(ctx.group_id);
+        ctx.fail(Failure::new(FakeError::bot_blocked()).chat(user).times(2));
+        ctx.fail(Failure::new(FakeError::Network).method("DeleteMessage"));
+
+        // Only the first two messages to the user fail, the group is fine
+        for _ in 0..2 {
+            let result = bot.send_message(user, "Hi").await;
+            assert!(matches!(
+                result,
+                Err(RequestError::Api(ApiError::BotBlocked))
+            ));
+        }
+        let sent = bot.send_message(group, "Hi").await.unwrap();
+        bot.send_message(user, "Hi").await.unwrap();
+
+        // And the deletes always fail, without touching the history
+        let result = bot.delete_message(group, sent.id).await;
+        assert!(matches!(result, Err(RequestError::Network(_))));
+        assert_eq!(bot.history().lock().unwrap().get_messages(group).len(), 1);
+        assert_eq!(ctx.get_sent_requests().len(), 5); // The failed requests are in the journal
+    }
+}
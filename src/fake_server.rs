@@ -0,0 +1,413 @@
+#![allow(dead_code)]
+use std::{
+    convert::Infallible,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hyper::{
+    body::to_bytes,
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use serde_json::{json, Value};
+use teloxide::{
+    prelude::*,
+    types::{ChatId, Update},
+    RequestError,
+};
+use tokio::{sync::oneshot, time::Instant};
+use url::Url;
+
+use crate::history::FakeHistory;
+use crate::intercept::{make_bot_json, make_chat_json, make_sent_message_json, RETURNS_TRUE};
+use crate::test_utils::get_bot;
+
+/*
+    A fake telegram server
+
+    TestBot intercepts the requests before they are sent, but it can only do that for the bot in
+    the tests. The fake server goes one step further: it's a real http server on your computer,
+    that answers like the Bot API does. Any `Bot` can be pointed at it with `set_api_url`, so the
+    whole bot, with the polling and the Dispatcher from main.rs, can run without the internet:
+
+    let server = FakeServer::start();
+    server.push_update(update); // What the bot will get from getUpdates
+    let bot = server.bot();     // A normal Bot, that sends everything to the fake server
+
+    It knows only the most common methods (see `fake_result`), and the ones that return `true`
+    (`RETURNS_TRUE` in intercept.rs). teloxide can't parse the requests back, so it can't use the
+    responses from intercept.rs for the rest, and answers them with an error, instead of a `true`
+    that the bot wouldn't be able to parse anyway. Files can't be uploaded to it, only sent by the
+    file id.
+*/
+
+#[derive(Clone, Debug)]
+pub struct ServerCall {
+    pub method: String, // Like in the url, e.g. "sendMessage"
+    pub json: Value,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    calls: Mutex<Vec<ServerCall>>,
+    updates: Mutex<Vec<Update>>, // The updates that the bot didn't confirm yet
+    next_update_id: Mutex<i32>,
+    history: Mutex<FakeHistory>, // The same history as the TestBot has, see history.rs
+}
+
+pub struct FakeServer {
+    url: Url,
+    state: Arc<ServerState>,
+    stop: Option<oneshot::Sender<()>>, // The server stops, when the FakeServer is dropped
+}
+
+impl FakeServer {
+    pub fn start() -> Self {
+        // Port 0 means "any free port", so the tests can each start their own server
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        let state = Arc::new(ServerState::default());
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_connection| {
+            let state = service_state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |request| handle(state.clone(), request))) }
+        });
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                stopped.await.ok();
+            });
+        tokio::spawn(server);
+
+        Self {
+            url: Url::parse(&format!("http://{address}")).unwrap(),
+            state,
+            stop: Some(stop),
+        }
+    }
+
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    pub fn bot(&self) -> Bot {
+        // The default test bot, but it talks only to this server
+        get_bot().set_api_url(self.url())
+    }
+
+    pub fn push_update(&self, mut update: Update) {
+        // The bot will get it the next time it calls getUpdates. The ids are given by the server,
+        // like telegram does it, so you don't need to change them in every update
+        let mut next_update_id = self.state.next_update_id.lock().unwrap();
+        update.id = *next_update_id;
+        *next_update_id += 1;
+        self.state.updates.lock().unwrap().push(update);
+    }
+
+    pub fn get_calls(&self) -> Vec<ServerCall> {
+        // Every request the server got, except getUpdates, there are too many of them
+        self.state.calls.lock().unwrap().clone()
+    }
+
+    pub fn get_calls_by_method(&self, method: &str) -> Vec<ServerCall> {
+        self.get_calls()
+            .into_iter()
+            .filter(|call| call.method == method)
+            .collect()
+    }
+
+    pub fn get_messages(&self, chat_id: ChatId) -> Vec<Message> {
+        // The messages in the chat, as they are now
+        self.state.history.lock().unwrap().get_messages(chat_id)
+    }
+
+    pub async fn wait_for_calls(&self, method: &str, count: usize) {
+        // The bot works in the background, so the test has to wait for it to answer
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while self.get_calls_by_method(method).len() < count {
+            if Instant::now() > deadline {
+                panic!(
+                    "The server didn't get {count} {method} requests, it got: {:#?}",
+                    self.get_calls()
+                );
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop.send(()).ok();
+        }
+    }
+}
+
+async fn handle(
+    state: Arc<ServerState>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    // The url is /bot<token>/<method>. Telegram doesn't care about the case of the method, and
+    // teloxide sends "SendMessage", so it's changed to "sendMessage", like in the docs
+    let mut method = request
+        .uri()
+        .path()
+        .rsplit('/')
+        .next()
+        .unwrap_or("")
+        .to_string();
+    if let Some(first) = method.get_mut(..1) {
+        first.make_ascii_lowercase();
+    }
+    let is_multipart = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("multipart/"));
+    let body = to_bytes(request.into_body()).await.unwrap_or_default();
+    let json: Value = serde_json::from_slice(&body).unwrap_or(json!({}));
+
+    let response = if method.is_empty() {
+        // Like "/bot<token>/", telegram answers the same to the methods it doesn't know
+        json!({ "ok": false, "error_code": 404, "description": "Not Found" })
+    } else if is_multipart {
+        json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: the fake server can't receive files, send them by the file id"
+        })
+    } else if method == "getUpdates" {
+        get_updates(&state, &json).await
+    } else {
+        state.calls.lock().unwrap().push(ServerCall {
+            method: method.clone(),
+            json: json.clone(),
+        });
+        respond(&state, &method, &json)
+    };
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(response.to_string()))
+        .unwrap())
+}
+
+async fn get_updates(state: &ServerState, request: &Value) -> Value {
+    // The bot confirms the updates by asking only for the ones after them, so the older ones are
+    // removed. If there is nothing new, it waits for the `timeout`, like telegram does
+    let offset = request["offset"].as_i64().unwrap_or(0);
+    let timeout = request["timeout"].as_u64().unwrap_or(0);
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    loop {
+        {
+            let mut updates = state.updates.lock().unwrap();
+            updates.retain(|update| update.id as i64 >= offset);
+            if !updates.is_empty() || Instant::now() >= deadline {
+                return json!({ "ok": true, "result": *updates });
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+fn respond(state: &ServerState, method: &str, request: &Value) -> Value {
+    let Some(result) = fake_result(method, request) else {
+        return json!({
+            "ok": false,
+            "error_code": 501,
+            "description": format!("Not Implemented: the fake server doesn't know what {method} returns")
+        });
+    };
+    // The history has the same names of the methods as teloxide, like "SendMessage"
+    let mut history_method = method.to_string();
+    if let Some(first) = history_method.get_mut(..1) {
+        first.make_ascii_uppercase();
+    }
+    let applied = state
+        .history
+        .lock()
+        .unwrap()
+        .apply_json(&history_method, request, result);
+    match applied {
+        Ok((result, _)) => json!({ "ok": true, "result": result }),
+        // The history only returns the telegram errors, they have the same text as in telegram
+        Err(RequestError::Api(error)) => json!({
+            "ok": false,
+            "error_code": 400,
+            "description": error.to_string()
+        }),
+        Err(error) => panic!("Unexpected error from the history: {error}"),
+    }
+}
+
+fn fake_result(method: &str, request: &Value) -> Option<Value> {
+    // The same responses as the TestBot makes in intercept.rs, or None if the server doesn't know
+    // the method
+    if request.get("inline_message_id").is_some() {
+        return Some(json!(true)); // The bot doesn't see the inline messages, so it gets only `true`
+    }
+    let text = json!({
+        "text": request["text"],
+        "entities": request.get("entities").unwrap_or(&json!([]))
+    });
+    let result = match method {
+        "getMe" => make_bot_json(),
+        "getChat" => make_chat_json(&request["chat_id"]),
+        "sendMessage" | "editMessageText" => make_sent_message_json(request, text),
+        // The history knows the rest of the message, it's only the keyboard that changes
+        "editMessageReplyMarkup" => {
+            make_sent_message_json(request, json!({ "text": "", "entities": [] }))
+        }
+        "sendDice" => make_sent_message_json(
+            request,
+            json!({ "dice": { "emoji": request.get("emoji").unwrap_or(&json!("🎲")), "value": 1 } }),
+        ),
+        "copyMessage" => json!({ "message_id": 1 }),
+        // answerCallbackQuery, deleteMessage, deleteWebhook, setMyCommands, sendChatAction, etc
+        _ if RETURNS_TRUE
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(method)) =>
+        {
+            json!(true)
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intercept::InterceptMode;
+    use crate::operation::Operation;
+    use crate::test_bot::TestBot;
+    use crate::test_utils::*;
+    use crate::{add_adaptors, make_dispatcher, text, State};
+    use teloxide::update_listeners::Polling;
+
+    #[tokio::test]
+    async fn test_the_dispatcher_with_the_fake_server() {
+        // The same dispatcher as in main.rs, with the polling and everything, only the bot talks
+        // to the fake server instead of telegram, and the states are in memory
+        let server = FakeServer::start();
+        let ctx = TestContext::new();
+        let bot = TestBot::with_mode(server.bot(), InterceptMode::Send);
+        let mut dispatcher = make_dispatcher(bot.clone(), ctx.storage.clone());
+        let shutdown = dispatcher.shutdown_token();
+        // main.rs polls with a 10 seconds timeout, and the dispatcher can stop only after it ends,
+        // so here it's 1 second, otherwise the test would wait for 10 seconds
+        let polling = Polling::builder(add_adaptors(bot))
+            .timeout(Duration::from_secs(1))
+            .build();
+
+        let user = async {
+            server.push_update(make_message_update(ctx.make_message(
+                "/start",
+                true,
+                ChatType::Private,
+            )));
+            server.wait_for_calls("sendMessage", 1).await;
+
+            // The user presses the button on the message that the bot sent
+            let keyboard_message = server.get_messages(ChatId(ctx.user_id)).pop().unwrap();
+            let mut call = ctx.make_callback_query("add", ChatType::Private);
+            call.message = Some(keyboard_message);
+            server.push_update(make_callback_query_update(call));
+            server.wait_for_calls("sendMessage", 2).await;
+
+            // Stops the polling, like ctrl+c would do
+            shutdown.shutdown().unwrap().await;
+        };
+        tokio::join!(
+            dispatcher.dispatch_with_listener(
+                polling,
+                LoggingErrorHandler::with_custom_text("An error from the update listener"),
+            ),
+            user
+        );
+
+        let texts: Vec<String> = server
+            .get_calls_by_method("sendMessage")
+            .iter()
+            .map(|call| call.json["text"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            texts,
+            [text::WHAT_DO_YOU_WANT, text::ENTER_THE_FIRST_NUMBER]
+        );
+        assert_eq!(
+            server.get_calls_by_method("editMessageReplyMarkup").len(),
+            1
+        );
+        assert_eq!(
+            ctx.get_state().await,
+            State::GetFirstNumber {
+                operation: Operation::Add
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_a_request_without_a_method() {
+        // The server answers like telegram, and keeps working
+        let server = FakeServer::start();
+        let url = server.url().join("/bot1234567890:TEST_TOKEN/").unwrap();
+        let response: Value = reqwest::Client::new()
+            .post(url)
+            .json(&json!({}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response["error_code"], 404);
+        server.bot().get_me().await.unwrap();
+        assert!(server.get_calls_by_method("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_a_method_that_the_server_doesnt_know() {
+        // sendLocation returns a message, and the server can't make it, so the bot gets an error
+        // instead of a `true` that it can't parse
+        let server = FakeServer::start();
+        let bot = server.bot();
+        let result = bot.send_location(ChatId(TEST_USER_ID), 50.45, 30.52).await;
+        let Err(RequestError::Api(teloxide::ApiError::Unknown(description))) = result else {
+            panic!("Expected an error from the fake server, got {result:?}");
+        };
+        assert!(description.contains("sendLocation"));
+        // But the ones that return `true` are fine
+        bot.send_chat_action(ChatId(TEST_USER_ID), teloxide::types::ChatAction::Typing)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_the_fake_server_errors() {
+        // A plain Bot, without any TestBot, gets the same errors as from telegram
+        let server = FakeServer::start();
+        let bot = server.bot();
+        let message = bot
+            .send_message(ChatId(TEST_USER_ID), "Hello")
+            .await
+            .unwrap();
+        bot.delete_message(message.chat.id, message.id)
+            .await
+            .unwrap();
+
+        let result = bot
+            .edit_message_text(message.chat.id, message.id, "Edited")
+            .await;
+        assert!(matches!(
+            result,
+            Err(RequestError::Api(teloxide::ApiError::MessageToEditNotFound))
+        ));
+    }
+}
@@ -1,13 +1,5 @@
-use crate::{
-    intercept::{TestingIntercept, TestingInterceptAndReturnMessage},
-    text, HandlerResult, MyDialogue, State,
-};
-use teloxide::{
-    dispatching::dialogue::GetChatId,
-    macros::BotCommands,
-    prelude::*,
-    types::{InlineKeyboardButton, InlineKeyboardMarkup},
-};
+use crate::{operation::Operation, text, HandlerResult, MyDialogue, State};
+use teloxide::{macros::BotCommands, prelude::*, types::MessageKind, RequestError};
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
@@ -16,56 +8,95 @@ pub enum StartCommand {
     Start,
 }
 
+pub fn get_topic(message: &Message) -> Option<i32> {
+    // The forum topic the message is in. Replies in usual groups have a thread id too, but they
+    // aren't topics, so only the topic messages count
+    let is_topic_message = match &message.kind {
+        MessageKind::Common(common) => common.is_topic_message,
+        _ => false, // The service messages, like "the topic was created"
+    };
+    message.thread_id.filter(|_| is_topic_message)
+}
+
+fn reply<B: Requester>(bot: &B, message: &Message, text: impl Into<String>) -> B::SendMessage {
+    // Sends the text into the chat of the message. In a forum it goes into the same topic,
+    // without message_thread_id telegram would put it into "General"
+    let request = bot.send_message(message.chat.id, text);
+    match get_topic(message) {
+        Some(thread_id) => request.message_thread_id(thread_id),
+        None => request,
+    }
+}
+
 /*
     Just some simple example handlers to test
+
+    They take any `B: Requester` instead of `Bot`, so the real bot can pass in a `Bot`, and the
+    tests can pass in a `TestBot`, that doesn't send anything to telegram
 */
 
-pub async fn start(bot: Bot, dialogue: MyDialogue, msg: Message) -> HandlerResult {
-    let keyboard = InlineKeyboardMarkup::new([[
-        InlineKeyboardButton::callback("Add", "add"),
-        InlineKeyboardButton::callback("Subtract", "subtract"),
-    ]]);
-    bot.send_message(msg.chat.id, text::WHAT_DO_YOU_WANT)
-        .reply_markup(keyboard)
-        .intercept()
+pub async fn start<B: Requester<Err = RequestError>>(
+    bot: B,
+    dialogue: MyDialogue,
+    msg: Message,
+) -> HandlerResult {
+    // The keyboard is only for the one who sent /start, the others in the group get their own
+    let owner = msg.from().map(|user| user.id);
+    reply(&bot, &msg, text::WHAT_DO_YOU_WANT)
+        .reply_markup(Operation::keyboard(owner))
         .await?;
     dialogue.update(State::WhatDoYouWant).await?;
     Ok(())
 }
 
-pub async fn what_is_the_first_number(
-    bot: Bot,
+pub async fn what_is_the_first_number<B: Requester<Err = RequestError>>(
+    bot: B,
     dialogue: MyDialogue,
     call: CallbackQuery,
 ) -> HandlerResult {
-    let chat_id = call.clone().chat_id().unwrap();
-    bot.edit_message_reply_markup(chat_id, call.message.unwrap().id)
-        .intercept()
-        .await?;
-    bot.send_message(chat_id, text::ENTER_THE_FIRST_NUMBER)
-        .intercept()
-        .await?;
-    dialogue
-        .update(State::GetFirstNumber {
-            operation: call.data.unwrap(),
-        })
+    // Anyone can send any data, not only the one from our buttons
+    let button = call.data.as_deref().and_then(Operation::from_button_data);
+    let Some((operation, owner)) = button else {
+        // The user stays in the same state, and can press a real button
+        bot.answer_callback_query(call.id)
+            .text(text::UNKNOWN_OPERATION)
+            .await?;
+        return Ok(());
+    };
+    if owner.is_some_and(|owner| owner != call.from.id) {
+        // Someone else's keyboard in a group. Their keyboard and their state stay as they are
+        bot.answer_callback_query(call.id)
+            .text(text::NOT_YOUR_KEYBOARD)
+            .await?;
+        return Ok(());
+    }
+    let Some(message) = call.message else {
+        // The buttons under inline messages come without the message, so there is nowhere to ask
+        // for the numbers
+        bot.answer_callback_query(call.id)
+            .text(text::NO_CHAT_FOR_THE_BUTTON)
+            .await?;
+        return Ok(());
+    };
+    // The message is the one with the keyboard
+    bot.edit_message_reply_markup(message.chat.id, message.id)
         .await?;
+    reply(&bot, &message, text::ENTER_THE_FIRST_NUMBER).await?;
+    dialogue.update(State::GetFirstNumber { operation }).await?;
     Ok(())
 }
 
-pub async fn what_is_the_second_number(
-    bot: Bot,
+pub async fn what_is_the_second_number<B: Requester<Err = RequestError>>(
+    bot: B,
     dialogue: MyDialogue,
     message: Message,
-    state_data: String,
+    state_data: Operation,
 ) -> HandlerResult {
     let message_text = match message.text() {
         // Just extracting the text from the message
         Some(text) => text,
         None => {
-            bot.send_message(message.chat.id, text::PLEASE_SEND_TEXT)
-                .intercept()
-                .await?;
+            reply(&bot, &message, text::PLEASE_SEND_TEXT).await?;
             return Ok(());
         }
     };
@@ -73,15 +104,11 @@ pub async fn what_is_the_second_number(
         // And then parsing it
         Ok(number) => number,
         Err(_) => {
-            bot.send_message(message.chat.id, text::PLEASE_ENTER_A_NUMBER)
-                .intercept()
-                .await?;
+            reply(&bot, &message, text::PLEASE_ENTER_A_NUMBER).await?;
             return Ok(());
         }
     };
-    bot.send_message(message.chat.id, text::ENTER_THE_SECOND_NUMBER)
-        .intercept()
-        .await?;
+    reply(&bot, &message, text::ENTER_THE_SECOND_NUMBER).await?;
     dialogue
         .update(State::GetSecondNumber {
             first_number,
@@ -91,152 +118,349 @@ pub async fn what_is_the_second_number(
     Ok(())
 }
 
-pub async fn get_result(
-    bot: Bot,
+pub async fn get_result<B: Requester<Err = RequestError>>(
+    bot: B,
     dialogue: MyDialogue,
     message: Message,
-    state_data: (i32, String),
+    state_data: (i32, Operation),
 ) -> HandlerResult {
     let message_text = match message.text() {
         // Who cares about DRY anyway
         Some(text) => text,
         None => {
-            bot.send_message(message.chat.id, text::PLEASE_SEND_TEXT)
-                .intercept()
-                .await?;
+            reply(&bot, &message, text::PLEASE_SEND_TEXT).await?;
             return Ok(());
         }
     };
     let second_number = match message_text.parse::<i32>() {
         Ok(number) => number,
         Err(_) => {
-            bot.send_message(message.chat.id, text::PLEASE_ENTER_A_NUMBER)
-                .intercept()
-                .await?;
+            reply(&bot, &message, text::PLEASE_ENTER_A_NUMBER).await?;
             return Ok(());
         }
     };
 
     let (first_number, operation) = state_data;
-    let result = match operation.as_str() {
-        "add" => first_number + second_number,
-        "subtract" => first_number - second_number,
-        _ => unreachable!(),
+    let result = match operation.apply(first_number, second_number) {
+        Some(result) => result,
+        None => {
+            // Like dividing by zero, the user can try another second number
+            reply(&bot, &message, text::CANT_CALCULATE).await?;
+            return Ok(());
+        }
     };
 
-    bot.send_message(
-        message.chat.id,
+    reply(
+        &bot,
+        &message,
         text::YOUR_RESULT.to_owned() + result.to_string().as_str(),
     )
-    .intercept()
     .await?;
     dialogue.update(State::default()).await?;
     Ok(())
 }
 
+pub async fn ignore_update() -> HandlerResult {
+    // For the updates that the bot sees, but doesn't have to answer
+    Ok(())
+}
+
+pub async fn ignore_button<B: Requester<Err = RequestError>>(
+    bot: B,
+    call: CallbackQuery,
+) -> HandlerResult {
+    // The presses that the bot doesn't wait for, like an old keyboard, or someone else's one.
+    // They still have to be answered, otherwise the button keeps loading on the user's screen
+    let owner = call
+        .data
+        .as_deref()
+        .and_then(Operation::from_button_data)
+        .and_then(|(_, owner)| owner);
+    let answer = bot.answer_callback_query(call.id);
+    match owner {
+        Some(owner) if owner != call.from.id => answer.text(text::NOT_YOUR_KEYBOARD).await?,
+        _ => answer.await?,
+    };
+    Ok(())
+}
+
 #[cfg(test)] // This prevents it from compiling in non-test mode
 mod tests {
-    use crate::test_utils::*;
+    use crate::{
+        failures::{Failure, FakeError},
+        intercept::SentPayload,
+        scenario::Scenario,
+        test_utils::*,
+    };
 
     use super::*;
-    use serial_test::serial;
+    use teloxide::{
+        types::{DiceEmoji, InlineKeyboardButton, InlineKeyboardMarkup, Recipient},
+        ApiError,
+    };
 
     #[tokio::test]
-    #[serial] // Because everything is async, and we are testing the same user, we need this.
-              // Otherwise, there is a lot of race conditions.
     async fn test_start() {
-        full_reset_test_user().await; // We need to reset everything, because i have encountered
-                                      // SUCH horrible bugs due to not resetting, they were IMPOSSIBLE to debug.
-                                      // Just add this to the start of every test and you will be ok.
+        let ctx = TestContext::new(); // Every test makes its own context, with its own user, its
+                                      // own sent requests and its own storage. That way the tests
+                                      // can't break each other, and they can all run at the same time.
         let state = State::default();
-        let message = make_message("/start", true, ChatType::Private);
+        let message = ctx.make_message("/start", true, ChatType::Private);
         // Create the environment that the test needs. In this case, it is just the state and the
         // message update, but if there is some db data or something, you need to redifine it here.
 
-        run_update(make_message_update(message), state)
+        ctx.run_update(make_message_update(message), state)
             .await
             .unwrap();
         // Actually running the update
 
-        check_the_state_and_text(State::WhatDoYouWant, text::WHAT_DO_YOU_WANT).await;
+        ctx.check_the_state_and_text(State::WhatDoYouWant, text::WHAT_DO_YOU_WANT)
+            .await;
         // Checking the state and the text. If you need to check more stuff,
         // like db or reply markup - add it here.
     }
 
     #[tokio::test]
-    #[serial]
+    async fn test_start_keyboard() {
+        let ctx = TestContext::new();
+        let message = ctx.make_message("/start", true, ChatType::Private);
+        ctx.run_update(make_message_update(message), State::default())
+            .await
+            .unwrap();
+
+        // The whole keyboard, with the callback data. Every button knows whose keyboard it is
+        ctx.check_the_keyboard(InlineKeyboardMarkup::new([
+            [
+                InlineKeyboardButton::callback("Add", format!("add:{}", ctx.user_id)),
+                InlineKeyboardButton::callback("Subtract", format!("subtract:{}", ctx.user_id)),
+            ],
+            [
+                InlineKeyboardButton::callback("Multiply", format!("multiply:{}", ctx.user_id)),
+                InlineKeyboardButton::callback("Divide", format!("divide:{}", ctx.user_id)),
+            ],
+            [
+                InlineKeyboardButton::callback("Modulo", format!("modulo:{}", ctx.user_id)),
+                InlineKeyboardButton::callback("Power", format!("power:{}", ctx.user_id)),
+            ],
+        ]));
+        // Or just the texts of the buttons, if the data doesn't matter
+        ctx.check_the_keyboard_labels(&[
+            &["Add", "Subtract"],
+            &["Multiply", "Divide"],
+            &["Modulo", "Power"],
+        ]);
+
+        // Now the user presses the button on the message that the bot just sent
+        let keyboard_message = ctx.get_last_message();
+        let call = ctx.press_button(&keyboard_message, "Subtract");
+        assert_eq!(call.data, Some(format!("subtract:{}", ctx.user_id)));
+        ctx.run_update(make_callback_query_update(call), ctx.get_state().await)
+            .await
+            .unwrap();
+
+        // The keyboard is removed from exactly that message
+        let edit = ctx.get_sent_requests_by_method("EditMessageReplyMarkup")[0].clone();
+        assert_eq!(edit.message_id, keyboard_message.message_id);
+        ctx.check_the_keyboard_labels(&[]);
+        ctx.check_the_state_and_text(
+            State::GetFirstNumber {
+                operation: Operation::Subtract,
+            },
+            text::ENTER_THE_FIRST_NUMBER,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_start_when_the_user_blocked_the_bot() {
+        let ctx = TestContext::new();
+        ctx.fail(Failure::new(FakeError::bot_blocked()).method("SendMessage"));
+        let message = ctx.make_message("/start", true, ChatType::Private);
+        ctx.get_dialogue().update(State::Start).await.unwrap();
+
+        // The handler gives the error to the dispatcher, and doesn't go to the next state, because
+        // the user never saw the question
+        let result = ctx.try_dispatch(make_message_update(message)).await;
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RequestError>(),
+            Some(RequestError::Api(ApiError::BotBlocked))
+        ));
+        assert_eq!(ctx.get_state().await, State::Start);
+    }
+
+    #[tokio::test]
     async fn test_what_is_the_first_number() {
-        full_reset_test_user().await;
+        let ctx = TestContext::new();
         // Everything else is the same
         let state = State::WhatDoYouWant;
-        let call = make_callback_query("add", ChatType::Private);
-        run_update(make_callback_query_update(call), state)
+        let call = ctx.make_callback_query("add", ChatType::Private);
+        ctx.run_update(make_callback_query_update(call), state)
             .await
             .unwrap();
 
-        check_the_state_and_text(
+        ctx.check_the_state_and_text(
             State::GetFirstNumber {
-                operation: "add".to_string(),
+                operation: Operation::Add,
             },
             text::ENTER_THE_FIRST_NUMBER,
         )
         .await;
+
+        // This handler sends two requests, and we can check both of them
+        ctx.check_the_sent_methods(&["EditMessageReplyMarkup", "SendMessage"]);
+        let edit = ctx.get_sent_requests_by_method("EditMessageReplyMarkup")[0].clone();
+        assert_eq!(edit.chat_id, Some(Recipient::Id(ChatId(ctx.user_id))));
+        let SentPayload::EditMessageReplyMarkup(edit) = edit.payload else {
+            panic!("Expected EditMessageReplyMarkup, got {:?}", edit.payload);
+        };
+        // The keyboard has to be removed, so the user can't press it twice
+        assert_eq!(edit.reply_markup, None);
+    }
+
+    #[tokio::test]
+    async fn test_an_unknown_operation() {
+        // Someone sent their own callback data, not from our keyboard. The bot doesn't panic, it
+        // just tells them, and waits for a real button
+        let ctx = TestContext::new();
+        let call = ctx.make_callback_query("sqrt", ChatType::Private);
+        ctx.run_update(make_callback_query_update(call), State::WhatDoYouWant)
+            .await
+            .unwrap();
+
+        ctx.check_the_sent_methods(&["AnswerCallbackQuery"]);
+        let answer = ctx.get_sent_requests().pop().unwrap();
+        assert_eq!(answer.json["text"], text::UNKNOWN_OPERATION);
+        assert_eq!(ctx.get_state().await, State::WhatDoYouWant);
+    }
+
+    #[tokio::test]
+    async fn test_a_button_without_a_message() {
+        // The buttons under inline messages don't have the message, the bot can't answer there.
+        // They don't have a chat either, so the schema doesn't even give them a dialogue, and the
+        // handler is called directly, to check that it doesn't rely on that
+        let ctx = TestContext::new();
+        let mut call = ctx.make_callback_query("add", ChatType::Private);
+        call.message = None;
+        call.inline_message_id = Some("1".to_string());
+        ctx.get_dialogue()
+            .update(State::WhatDoYouWant)
+            .await
+            .unwrap();
+        what_is_the_first_number(ctx.bot.clone(), ctx.get_dialogue(), call)
+            .await
+            .unwrap();
+
+        ctx.check_the_sent_methods(&["AnswerCallbackQuery"]);
+        let answer = ctx.get_sent_requests().pop().unwrap();
+        assert_eq!(answer.json["text"], text::NO_CHAT_FOR_THE_BUTTON);
+        assert_eq!(ctx.get_state().await, State::WhatDoYouWant);
+    }
+
+    #[tokio::test]
+    async fn test_division_by_zero() {
+        let ctx = TestContext::new();
+        let state = State::GetSecondNumber {
+            first_number: 1,
+            operation: Operation::Divide,
+        };
+        let message = ctx.make_message("0", false, ChatType::Private);
+        ctx.run_update(make_message_update(message), state.clone())
+            .await
+            .unwrap();
+
+        // The first number is still there, so the user only has to enter another second one
+        ctx.check_the_state_and_text(state, text::CANT_CALCULATE)
+            .await;
     }
 
     #[tokio::test]
-    #[serial]
     async fn test_message_errors() {
-        full_reset_test_user().await;
+        let ctx = TestContext::new();
         let state = State::GetFirstNumber {
-            operation: "add".to_string(),
+            operation: Operation::Add,
         };
-        let message = make_message("not a number", false, ChatType::Private);
-        run_update(make_message_update(message), state)
+        let message = ctx.make_message("not a number", false, ChatType::Private);
+        ctx.run_update(make_message_update(message), state)
             .await
             .unwrap();
 
-        check_the_state_and_text(
+        ctx.check_the_state_and_text(
             State::GetFirstNumber {
                 // Technically, this is redundant, and you can check just the text
-                operation: "add".to_string(),
+                operation: Operation::Add,
             },
             text::PLEASE_ENTER_A_NUMBER,
         )
         .await;
 
-        // Because we use the same state, we can just add the next test to the same function!
+        // Because we use the same context, we can just add the next test to the same function!
         // Dont overuse it though, it can get confusing really fast
-        let message = make_photo(false, ChatType::Private);
-        run_update(make_message_update(message), get_state().await) // This is using the state that is
-            // stored in redis, in this case - the same state as before, but if the previous test changed
-            // the state, the new updated state will be returned by get_state() function.
+        let message = ctx.make_photo(false, ChatType::Private);
+        ctx.run_update(make_message_update(message), ctx.get_state().await) // This is using the
+            // state that is stored in the context, in this case - the same state as before, but if
+            // the previous update changed the state, the new updated state will be returned.
             .await
             .unwrap();
-        check_the_state_and_text(
+        ctx.check_the_state_and_texts(
+            // And this checks every text that was sent in this test, not just the last one
             State::GetFirstNumber {
-                operation: "add".to_string(),
+                operation: Operation::Add,
             },
-            text::PLEASE_SEND_TEXT,
+            &[text::PLEASE_ENTER_A_NUMBER, text::PLEASE_SEND_TEXT],
         )
         .await;
     }
 
     #[tokio::test]
-    #[serial]
+    async fn test_every_media_is_not_a_number() {
+        let ctx = TestContext::new();
+        let state = State::GetFirstNumber {
+            operation: Operation::Add,
+        };
+        // Users send all kinds of stuff, and the bot has to ask for the text every time
+        let builder = ctx.message_builder(ChatType::Private);
+        let messages = [
+            builder.clone().document(),
+            builder.clone().video(),
+            builder.clone().animation(),
+            builder.clone().audio(),
+            builder.clone().voice(),
+            builder.clone().video_note(),
+            builder.clone().sticker(),
+            builder.clone().location(50.45, 30.52),
+            builder
+                .clone()
+                .venue(50.45, 30.52, "Test Venue", "Test Street, 1"),
+            builder.clone().contact("+1234567890", "Test"),
+            builder.clone().dice(DiceEmoji::Dice, 6),
+            builder.poll("Test?", &["Yes", "No"]),
+        ];
+        for message in messages {
+            ctx.run_update(make_message_update(message.build()), state.clone())
+                .await
+                .unwrap();
+        }
+
+        ctx.check_the_state_and_texts(state, &[text::PLEASE_SEND_TEXT; 12])
+            .await;
+    }
+
+    #[tokio::test]
     async fn test_what_is_the_second_number() {
-        full_reset_test_user().await;
+        let ctx = TestContext::new();
         let state = State::GetFirstNumber {
-            operation: "add".to_string(),
+            operation: Operation::Add,
         };
-        let message = make_message("1", false, ChatType::Private);
-        run_update(make_message_update(message), state)
+        let message = ctx.make_message("1", false, ChatType::Private);
+        ctx.run_update(make_message_update(message), state)
             .await
             .unwrap();
 
-        check_the_state_and_text(
+        ctx.check_the_state_and_text(
             State::GetSecondNumber {
                 first_number: 1,
-                operation: "add".to_string(),
+                operation: Operation::Add,
             },
             text::ENTER_THE_SECOND_NUMBER,
         )
@@ -244,33 +468,283 @@ mod tests {
     }
 
     #[tokio::test]
-    #[serial]
     async fn test_add_result() {
-        full_reset_test_user().await;
+        let ctx = TestContext::new();
         let state = State::GetSecondNumber {
             first_number: 1,
-            operation: "add".to_string(),
+            operation: Operation::Add,
         };
-        let message = make_message("2", false, ChatType::Private);
-        run_update(make_message_update(message), state)
+        let message = ctx.make_message("2", false, ChatType::Private);
+        ctx.run_update(make_message_update(message), state)
             .await
             .unwrap();
 
-        check_the_state_and_text(State::Start, &(text::YOUR_RESULT.to_owned() + "3")).await;
+        ctx.check_the_state_and_text(State::Start, &(text::YOUR_RESULT.to_owned() + "3"))
+            .await;
     }
 
     #[tokio::test]
-    #[serial]
     async fn test_subtract_result() {
+        let ctx = TestContext::new();
         let state = State::GetSecondNumber {
             first_number: 1,
-            operation: "subtract".to_string(),
+            operation: Operation::Subtract,
         };
-        let message = make_message("2", false, ChatType::Private);
-        run_update(make_message_update(message), state)
+        let message = ctx.make_message("2", false, ChatType::Private);
+        ctx.run_update(make_message_update(message), state)
+            .await
+            .unwrap();
+
+        ctx.check_the_state_and_text(State::Start, &(text::YOUR_RESULT.to_owned() + "-1"))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_the_whole_dialogue() {
+        // The same thing as the tests above, but all at once, like a real user would do it
+        Scenario::new()
+            .send("/start")
+            .expect_texts(&[text::WHAT_DO_YOU_WANT])
+            .expect_keyboard(&[
+                &["Add", "Subtract"],
+                &["Multiply", "Divide"],
+                &["Modulo", "Power"],
+            ])
+            .expect_state(State::WhatDoYouWant)
+            .press_button("Subtract")
+            .expect_texts(&[text::ENTER_THE_FIRST_NUMBER])
+            .expect_keyboard(&[]) // The keyboard was removed
+            .send_photo()
+            .expect_texts(&[text::PLEASE_SEND_TEXT])
+            .send("5")
+            .expect_texts(&[text::ENTER_THE_SECOND_NUMBER])
+            .expect_state(State::GetSecondNumber {
+                first_number: 5,
+                operation: Operation::Subtract,
+            })
+            .send("7")
+            .expect_texts(&[&(text::YOUR_RESULT.to_owned() + "-2")])
+            .expect_state(State::Start)
+            .run()
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_the_whole_dialogue_through_the_dispatcher() {
+        // The same thing again, but through the real Dispatcher from main.rs, not just the schema
+        let ctx = TestContext::new();
+        ctx.run_dispatcher(vec![make_message_update(ctx.make_message(
+            "/start",
+            true,
+            ChatType::Private,
+        ))])
+        .await;
+        // The dispatcher stops only when every update is handled, so everything is sent already
+        let call = ctx.press_button(&ctx.get_last_message(), "Add");
+        ctx.run_dispatcher(vec![
+            make_callback_query_update(call),
+            make_message_update(ctx.make_message("2", false, ChatType::Private)),
+            make_message_update(ctx.make_message("3", false, ChatType::Private)),
+        ])
+        .await;
+
+        ctx.check_the_state_and_texts(
+            State::Start,
+            &[
+                text::WHAT_DO_YOU_WANT,
+                text::ENTER_THE_FIRST_NUMBER,
+                text::ENTER_THE_SECOND_NUMBER,
+                &(text::YOUR_RESULT.to_owned() + "5"),
+            ],
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_the_dispatcher_survives_an_error() {
+        // The first answer fails, the default error handler only logs it, and the bot keeps working
+        let ctx = TestContext::new();
+        ctx.fail(
+            Failure::new(FakeError::Network)
+                .method("SendMessage")
+                .times(1),
+        );
+        let start = make_message_update(ctx.make_message("/start", true, ChatType::Private));
+        ctx.run_dispatcher(vec![start.clone(), start]).await;
+
+        // The dispatcher asks telegram who the bot is, before it starts
+        ctx.check_the_sent_methods(&["GetMe", "SendMessage", "SendMessage"]);
+        assert_eq!(ctx.get_state().await, State::WhatDoYouWant);
+    }
+
+    #[tokio::test]
+    async fn test_two_users_in_a_group() {
+        // Both start the calculator in the same group, but every one of them has their own state
+        let alice = TestContext::new();
+        let bob = alice.as_user(alice.another_user().first_name("Bob"));
+        let group = ChatType::Group;
+        alice
+            .dispatch(make_message_update(alice.make_message(
+                "/start",
+                true,
+                group.clone(),
+            )))
+            .await
+            .unwrap();
+        let keyboard = alice.get_last_message();
+        let call = alice.press_button(&keyboard, "Add");
+        alice
+            .dispatch(make_callback_query_update(call))
             .await
             .unwrap();
 
-        check_the_state_and_text(State::Start, &(text::YOUR_RESULT.to_owned() + "-1")).await;
+        // Bob just talks in the group, the bot doesn't answer him, and Alice's state stays
+        let sent_before = alice.get_sent_requests().len();
+        bob.dispatch(make_message_update(bob.make_message(
+            "5",
+            false,
+            group.clone(),
+        )))
+        .await
+        .unwrap();
+        assert_eq!(alice.get_sent_requests().len(), sent_before);
+        assert_eq!(bob.get_state_in(group.clone()).await, State::Start);
+
+        // And then he starts his own calculator, while Alice is still in the middle of hers
+        bob.dispatch(make_message_update(bob.make_message(
+            "/start",
+            true,
+            group.clone(),
+        )))
+        .await
+        .unwrap();
+        for number in ["2", "3"] {
+            let message = alice.make_message(number, false, group.clone());
+            alice.dispatch(make_message_update(message)).await.unwrap();
+        }
+        assert_eq!(
+            alice.get_sent_texts().last().unwrap(),
+            &(text::YOUR_RESULT.to_owned() + "5")
+        );
+        assert_eq!(alice.get_state_in(group.clone()).await, State::Start);
+        // Their private chats with the bot are separate from the group
+        assert_eq!(alice.get_state().await, State::Start);
+
+        // Alice starts again, and Bob presses a button on her keyboard instead of his own. He is
+        // only told that it isn't his, and nothing else changes for either of them
+        let bob_keyboard = bob.find_message_with_button("Add").unwrap();
+        alice
+            .dispatch(make_message_update(alice.make_message(
+                "/start",
+                true,
+                group.clone(),
+            )))
+            .await
+            .unwrap();
+        let alice_keyboard = alice.get_last_message();
+        let sent_before = alice.get_sent_requests().len();
+        let call = bob.press_button(&alice_keyboard, "Add");
+        bob.dispatch(make_callback_query_update(call))
+            .await
+            .unwrap();
+        let methods: Vec<&str> = alice.get_sent_requests()[sent_before..]
+            .iter()
+            .map(|request| request.method)
+            .collect();
+        assert_eq!(methods, ["AnswerCallbackQuery"]);
+        let answer = alice.get_sent_requests().pop().unwrap();
+        assert_eq!(answer.json["text"], text::NOT_YOUR_KEYBOARD);
+        let alice_message = alice.get_sent_message(&alice_keyboard).unwrap();
+        assert!(alice_message.reply_markup().is_some());
+        assert_eq!(
+            alice.get_state_in(group.clone()).await,
+            State::WhatDoYouWant
+        );
+        assert_eq!(bob.get_state_in(group.clone()).await, State::WhatDoYouWant);
+
+        // His own keyboard still works
+        let call = bob.press_button(&bob_keyboard, "Add");
+        bob.dispatch(make_callback_query_update(call))
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.get_state_in(group).await,
+            State::GetFirstNumber {
+                operation: Operation::Add
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_topics_in_a_forum() {
+        // The same user counts in two topics at once, and the bot answers each in its own topic
+        let ctx = TestContext::new();
+        for thread_id in [10, 20] {
+            let start = ctx.make_topic_message("/start", true, thread_id);
+            ctx.dispatch(make_message_update(start)).await.unwrap();
+        }
+        // Add in the first topic, the keyboard message knows its topic
+        let keyboard = ctx.get_sent_requests_by_method("SendMessage")[0].clone();
+        assert_eq!(keyboard.thread_id(), Some(10));
+        let call = ctx.press_button(&keyboard, "Add");
+        ctx.dispatch(make_callback_query_update(call))
+            .await
+            .unwrap();
+        for number in ["2", "3"] {
+            let message = ctx.make_topic_message(number, false, 10);
+            ctx.dispatch(make_message_update(message)).await.unwrap();
+        }
+
+        assert_eq!(ctx.get_state_in_topic(10).await, State::Start);
+        assert_eq!(ctx.get_state_in_topic(20).await, State::WhatDoYouWant);
+        let result = ctx.get_sent_requests().pop().unwrap();
+        assert_eq!(result.text(), Some("Your result: 5"));
+        assert_eq!(result.thread_id(), Some(10));
+        let sent = ctx.get_sent_requests_by_method("SendMessage");
+        let threads: Vec<Option<i32>> = sent.iter().map(|request| request.thread_id()).collect();
+        assert_eq!(threads, [Some(10), Some(20), Some(10), Some(10), Some(10)]);
+    }
+
+    #[tokio::test]
+    async fn test_replies_outside_of_topics() {
+        // Not a forum, the thread of a reply isn't a topic, so the bot doesn't send into it
+        let ctx = TestContext::new();
+        let message = ctx
+            .message_builder(ChatType::Supergroup)
+            .command("/start")
+            .thread_id(5)
+            .build();
+        ctx.dispatch(make_message_update(message)).await.unwrap();
+        ctx.check_the_thread(None);
+    }
+
+    #[tokio::test]
+    async fn test_the_whole_dialogue_snapshot() {
+        // The same dialogue, but instead of writing down every text, everything the bot sent is
+        // compared with src/snapshots/test_the_whole_dialogue_snapshot.snap
+        let ctx = Scenario::new()
+            .send("/start")
+            .press_button("Add")
+            .send("5")
+            .send("7")
+            .run()
+            .await;
+        ctx.check_the_snapshot("test_the_whole_dialogue_snapshot")
+            .await;
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Scenario diverged at step 2 (press the button \"Add\")")]
+    async fn test_scenario_shows_where_it_diverged() {
+        // The output has the diff of the texts:
+        // - "Enter the second number"
+        // + "Enter the first number"
+        Scenario::new()
+            .send("/start")
+            .expect_state(State::WhatDoYouWant)
+            .press_button("Add")
+            .expect_texts(&[text::ENTER_THE_SECOND_NUMBER])
+            .run()
+            .await;
     }
 }
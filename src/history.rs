@@ -0,0 +1,319 @@
+#![allow(dead_code)]
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use teloxide::{
+    types::{ChatId, Message, MessageId},
+    ApiError, RequestError,
+};
+
+use crate::identity::TestBotIdentity;
+use crate::intercept::{edits_a_message, make_chat_json, SentRequest, TEST_DATE};
+
+/*
+    The fake history of the chats
+
+    Telegram remembers every message in a chat, so the bot can edit or delete it later by its id.
+    The intercepted requests never get to telegram, so the TestBot keeps its own history: every
+    message it sends gets the next id in that chat, edits change the stored message, deletes remove
+    it, and if a request targets a message that isn't there, it gets the same error telegram would
+    return. The messages from the updates in tests are added here too (see TestContext::dispatch),
+    so the bot can edit and delete them as well.
+
+    Everything is kept as json, exactly like telegram would've sent it, the same as in intercept.rs
+*/
+
+pub type History = Arc<Mutex<FakeHistory>>;
+// Shared between the clones of the bot, the same way as the Journal in intercept.rs
+
+#[derive(Debug, Default)]
+pub struct FakeHistory {
+    chats: HashMap<String, ChatHistory>, // By the chat id, or the username of a channel
+}
+
+#[derive(Debug, Default)]
+struct ChatHistory {
+    last_id: i32,                   // The id of the newest message, deleted or not
+    messages: BTreeMap<i32, Value>, // BTreeMap keeps them sorted by id
+    chat: Option<Value>,            // The chat as the updates described it, if there were any
+}
+
+fn chat_key(chat_id: &Value) -> Option<String> {
+    // Chats can be targeted by the id, or by the @username if it is a channel
+    match chat_id {
+        Value::Number(id) => Some(id.to_string()),
+        Value::String(username) => Some(username.clone()),
+        _ => None,
+    }
+}
+
+impl FakeHistory {
+    pub fn add_message(&mut self, message: &Message) {
+        // A message that the bot didn't send, like the messages from the user. It keeps its id,
+        // and the next message of the bot will have a bigger one
+        let chat = self.chats.entry(message.chat.id.to_string()).or_default();
+        chat.last_id = chat.last_id.max(message.id.0);
+        chat.chat = Some(serde_json::to_value(&message.chat).unwrap());
+        chat.messages
+            .insert(message.id.0, serde_json::to_value(message).unwrap());
+    }
+
+    pub fn add_message_if_missing(&mut self, message: &Message) {
+        // For the messages with the buttons, that come with callback queries. If the bot sent
+        // that message, it's already here, with all the edits
+        if self.get_message(message.chat.id, message.id).is_none() {
+            self.add_message(message);
+        }
+    }
+
+    pub fn get_message(&self, chat_id: ChatId, message_id: MessageId) -> Option<Message> {
+        let message = self
+            .chats
+            .get(&chat_id.to_string())?
+            .messages
+            .get(&message_id.0)?;
+        Some(serde_json::from_value(message.clone()).unwrap())
+    }
+
+    pub fn get_sent_message(&self, request: &SentRequest) -> Option<Message> {
+        // How the message that this request sent or edited looks now
+        let chat = self.chats.get(&chat_key(&request.json["chat_id"])?)?;
+        let message = chat.messages.get(&request.message_id?.0)?;
+        Some(serde_json::from_value(message.clone()).unwrap())
+    }
+
+    pub fn get_messages(&self, chat_id: ChatId) -> Vec<Message> {
+        // Every message in the chat that wasn't deleted, from the oldest to the newest
+        self.chats
+            .get(&chat_id.to_string())
+            .map(|chat| {
+                chat.messages
+                    .values()
+                    .map(|message| serde_json::from_value(message.clone()).unwrap())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn apply<T: Serialize + DeserializeOwned>(
+        &mut self,
+        request: &mut SentRequest,
+        response: T,
+    ) -> Result<T, RequestError> {
+        // Changes the history the way the request would've changed it in telegram, and fixes the
+        // fake response to match it. The response is turned into json and back, so that it works
+        // the same way for every request type
+        let response = serde_json::to_value(response).unwrap();
+        let (response, new_message_id) =
+            self.apply_json(request.method, &request.json, response)?;
+        if new_message_id.is_some() {
+            request.message_id = new_message_id;
+        }
+        Ok(serde_json::from_value(response).unwrap())
+    }
+
+    pub fn apply_json(
+        &mut self,
+        method: &str,
+        request: &Value,
+        response: Value,
+    ) -> Result<(Value, Option<MessageId>), RequestError> {
+        // The same, but only with the jsons, the fake server (see fake_server.rs) uses it directly.
+        // Also returns the id of the new message, if the request sent one
+        let Some(key) = chat_key(&request["chat_id"]) else {
+            // Inline messages and requests without a chat, we don't know anything about them
+            return Ok((response, None));
+        };
+        let chat = self.chats.entry(key).or_default();
+        let message_id = request["message_id"].as_i64().unwrap_or(0) as i32;
+
+        match method {
+            "DeleteMessage" => match chat.messages.remove(&message_id) {
+                Some(_) => Ok((response, None)),
+                None => Err(RequestError::Api(ApiError::MessageToDeleteNotFound)),
+            },
+            method if edits_a_message(method) => {
+                let Some(stored) = chat.messages.get_mut(&message_id) else {
+                    return Err(RequestError::Api(ApiError::MessageToEditNotFound));
+                };
+                if response.get("message_id").is_none() {
+                    return Ok((response, None)); // StopPoll returns the poll, not the message
+                }
+                *stored = edit_message(method, stored, response);
+                Ok((stored.clone(), None))
+            }
+            "CopyMessage" => {
+                // Returns only the id, and we don't know what was in the copied message, so it
+                // is stored as an empty text
+                chat.last_id += 1;
+                let copy = json!({
+                    "message_id": chat.last_id,
+                    "date": TEST_DATE,
+                    "chat": chat.chat.clone().unwrap_or_else(|| make_chat_json(&request["chat_id"])),
+                    "from": TestBotIdentity::default().user(),
+                    "text": ""
+                });
+                chat.messages.insert(chat.last_id, copy);
+                let message_id = MessageId(chat.last_id);
+                Ok((json!({ "message_id": chat.last_id }), Some(message_id)))
+            }
+            _ if response.get("chat").is_some() => {
+                // A new message, it gets the next id
+                let response = chat.add_new_message(response);
+                Ok((response, Some(MessageId(chat.last_id))))
+            }
+            "SendMediaGroup" if response.is_array() => {
+                let messages: Vec<Value> = response
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|message| chat.add_new_message(message.clone()))
+                    .collect();
+                // The request sent a few messages, the id of the first one is remembered
+                let first_id = MessageId(chat.last_id - messages.len() as i32 + 1);
+                Ok((Value::Array(messages), Some(first_id)))
+            }
+            _ => Ok((response, None)),
+        }
+    }
+}
+
+impl ChatHistory {
+    fn add_new_message(&mut self, mut message: Value) -> Value {
+        self.last_id += 1;
+        message["message_id"] = json!(self.last_id);
+        if let Some(chat) = &self.chat {
+            // The fake response only guesses the chat from its id, but if the test sent an update
+            // from this chat, we know exactly what it is, like a group or a forum
+            message["chat"] = chat.clone();
+        }
+        self.messages.insert(self.last_id, message.clone());
+        message
+    }
+}
+
+fn edit_message(method: &str, stored: &Value, response: Value) -> Value {
+    // The fake response of an edit knows only what was in the request, so the rest is taken from
+    // the stored message
+    let mut edited = match method {
+        "EditMessageReplyMarkup" | "EditMessageCaption" => {
+            // Only the keyboard (and the caption) change, the rest of the message stays
+            let mut edited = stored.clone();
+            if method == "EditMessageCaption" {
+                for field in ["caption", "caption_entities"] {
+                    edited[field] = response.get(field).cloned().unwrap_or(Value::Null);
+                }
+            }
+            edited
+        }
+        // A new text, media or location replaces the old one completely
+        _ => response.clone(),
+    };
+    for field in [
+        "message_id",
+        "message_thread_id",
+        "is_topic_message",
+        "date",
+        "from",
+        "reply_to_message",
+    ] {
+        // A message can't be moved to another topic by an edit, so its thread stays too
+        edited[field] = stored.get(field).cloned().unwrap_or(Value::Null);
+    }
+    // Without a keyboard in the request, telegram removes the old one
+    edited["reply_markup"] = response.get("reply_markup").cloned().unwrap_or(Value::Null);
+    edited["edit_date"] = json!(TEST_DATE);
+    // Telegram doesn't send the empty fields, and teloxide doesn't expect them
+    if let Value::Object(fields) = &mut edited {
+        fields.retain(|_, value| !value.is_null());
+    }
+    edited
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use teloxide::{
+        prelude::*,
+        types::{InlineKeyboardButton, InlineKeyboardMarkup},
+    };
+
+    #[tokio::test]
+    async fn test_the_history_of_a_chat() {
+        let ctx = TestContext::new();
+        let bot = ctx.bot.clone();
+        let chat_id = ChatId(ctx.user_id);
+
+        // Every new message gets the next id
+        let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback("Yes", "yes")]]);
+        let first = bot
+            .send_message(chat_id, "First")
+            .reply_markup(keyboard)
+            .await
+            .unwrap();
+        let second = bot.send_message(chat_id, "Second").await.unwrap();
+        assert_eq!((first.id, second.id), (MessageId(1), MessageId(2)));
+
+        // An edit of the keyboard keeps the text
+        let edited = bot
+            .edit_message_reply_markup(chat_id, first.id)
+            .await
+            .unwrap();
+        assert_eq!(edited.text(), Some("First"));
+        assert_eq!(edited.reply_markup(), None);
+
+        // And an edit of the text changes the stored message
+        bot.edit_message_text(chat_id, second.id, "Second, edited")
+            .await
+            .unwrap();
+        let messages = bot.history().lock().unwrap().get_messages(chat_id);
+        let texts: Vec<&str> = messages
+            .iter()
+            .filter_map(|message| message.text())
+            .collect();
+        assert_eq!(texts, ["First", "Second, edited"]);
+
+        // The deleted message is gone, and can't be edited or deleted again
+        bot.delete_message(chat_id, first.id).await.unwrap();
+        assert_eq!(bot.history().lock().unwrap().get_messages(chat_id).len(), 1);
+        let edit = bot.edit_message_text(chat_id, first.id, "Too late").await;
+        assert!(matches!(
+            edit,
+            Err(RequestError::Api(ApiError::MessageToEditNotFound))
+        ));
+        let delete = bot.delete_message(chat_id, first.id).await;
+        assert!(matches!(
+            delete,
+            Err(RequestError::Api(ApiError::MessageToDeleteNotFound))
+        ));
+
+        // The id of a deleted message isn't used again
+        let third = bot.send_message(chat_id, "Third").await.unwrap();
+        assert_eq!(third.id, MessageId(3));
+        assert_eq!(ctx.get_sent_requests().len(), 8); // The failed requests are in the journal too
+    }
+
+    #[tokio::test]
+    async fn test_the_chat_of_the_answers() {
+        // Without any updates, the chat is guessed from the id, and the test group is a supergroup
+        let ctx = TestContext::new();
+        let sent = ctx
+            .bot
+            .send_message(ChatId(ctx.group_id), "Hi")
+            .await
+            .unwrap();
+        assert_eq!(sent.chat, ctx.make_chat(ChatType::Supergroup));
+
+        // But after an update from the chat, the answers are in exactly that chat
+        let message = ctx.make_message("/start", true, ChatType::Group);
+        ctx.dispatch(make_message_update(message)).await.unwrap();
+        let answer = ctx.get_last_message();
+        let answer = ctx.bot.history().lock().unwrap().get_sent_message(&answer);
+        assert_eq!(answer.unwrap().chat, ctx.make_chat(ChatType::Group));
+    }
+}
@@ -0,0 +1,212 @@
+#![allow(dead_code)]
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use teloxide::{
+    types::{Me, User, UserId},
+    Bot,
+};
+
+/*
+    The bot that the tests pretend to be
+
+    The tests never talk to telegram, so they don't need a real token. Every TestContext has a
+    `TestBotIdentity`, by default a made up bot with a made up token, so `cargo test` works right
+    after `git clone`, without any .env. If your handlers care about the name of the bot (like
+    commands with "/start@your_bot" in groups), give the context your own identity:
+
+    let ctx = TestContext::with_identity(TestBotIdentity {
+        username: "calculator_bot".to_string(),
+        ..Default::default()
+    });
+*/
+
+pub type FakeBot = TestBotIdentity;
+// The same thing, with the same name as FakeUser and FakeChat in builders.rs
+
+pub const TEST_BOT_TOKEN: &str = "1234567890:TEST_TOKEN_not_a_real_one";
+// It has the same shape as a real token, the bot id before the ":", but telegram won't accept it
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestBotIdentity {
+    pub token: String, // The id of the bot is taken from it, like in the real tokens
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: String,
+    pub can_join_groups: bool,
+    pub can_read_all_group_messages: bool,
+    pub supports_inline_queries: bool,
+}
+
+impl Default for TestBotIdentity {
+    fn default() -> Self {
+        Self {
+            token: TEST_BOT_TOKEN.to_string(),
+            first_name: "Test".to_string(),
+            last_name: Some("Bot".to_string()),
+            username: "test_bot".to_string(),
+            can_join_groups: false,
+            can_read_all_group_messages: false,
+            supports_inline_queries: true,
+        }
+    }
+}
+
+impl TestBotIdentity {
+    // The builder methods, like FakeUser has. There is no `.id()` to set, the id is in the token
+
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = token.into();
+        self
+    }
+
+    pub fn first_name(mut self, first_name: impl Into<String>) -> Self {
+        self.first_name = first_name.into();
+        self
+    }
+
+    pub fn last_name(mut self, last_name: Option<&str>) -> Self {
+        self.last_name = last_name.map(str::to_string);
+        self
+    }
+
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        // Every bot has a username, unlike the users
+        self.username = username.into();
+        self
+    }
+
+    pub fn can_join_groups(mut self, can_join_groups: bool) -> Self {
+        self.can_join_groups = can_join_groups;
+        self
+    }
+
+    pub fn can_read_all_group_messages(mut self, can_read_all_group_messages: bool) -> Self {
+        self.can_read_all_group_messages = can_read_all_group_messages;
+        self
+    }
+
+    pub fn supports_inline_queries(mut self, supports_inline_queries: bool) -> Self {
+        self.supports_inline_queries = supports_inline_queries;
+        self
+    }
+
+    pub fn id(&self) -> i64 {
+        // Every token starts with a bot id
+        self.token
+            .split(':')
+            .next()
+            .and_then(|id| id.parse().ok())
+            .unwrap_or_else(|| panic!("The token {:?} doesn't start with a bot id", self.token))
+    }
+
+    pub fn user(&self) -> User {
+        // The bot as a `User`, like in the `from` of its messages
+        User {
+            id: UserId(self.id() as u64),
+            is_bot: true,
+            first_name: self.first_name.clone(),
+            last_name: self.last_name.clone(),
+            username: Some(self.username.clone()),
+            language_code: Some("en".to_string()),
+            is_premium: false,
+            added_to_attachment_menu: false,
+        }
+    }
+
+    pub fn me(&self) -> Me {
+        // What get_me returns, the dispatcher gives it to the handlers
+        Me {
+            user: self.user(),
+            can_join_groups: self.can_join_groups,
+            can_read_all_group_messages: self.can_read_all_group_messages,
+            supports_inline_queries: self.supports_inline_queries,
+        }
+    }
+
+    pub fn bot(&self) -> Bot {
+        // A normal bot with this token. It can't send anything to telegram, so it has to be
+        // inside a TestBot, or pointed at the fake server (see fake_server.rs)
+        Bot::new(&self.token)
+    }
+
+    pub fn replace_the_default_bot<T: Serialize + DeserializeOwned>(&self, response: T) -> T {
+        // The fake responses in intercept.rs are made with the default bot, so if this identity
+        // is different, it takes its place everywhere in the response
+        if *self == Self::default() {
+            return response;
+        }
+        let mut json = serde_json::to_value(response).unwrap();
+        self.replace_in_json(&mut json, Self::default().id());
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn replace_in_json(&self, json: &mut Value, default_id: i64) {
+        match json {
+            Value::Object(fields) => {
+                let is_default_bot = fields.get("is_bot") == Some(&Value::Bool(true))
+                    && fields.get("id").and_then(Value::as_i64) == Some(default_id);
+                if is_default_bot {
+                    // get_me returns the flags too, the `from` of a message has only the user
+                    *json = match fields.contains_key("can_join_groups") {
+                        true => serde_json::to_value(self.me()).unwrap(),
+                        false => serde_json::to_value(self.user()).unwrap(),
+                    };
+                    return;
+                }
+                for value in fields.values_mut() {
+                    self.replace_in_json(value, default_id);
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    self.replace_in_json(value, default_id);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn get_bot_id() -> i64 {
+    // The id of the default test bot, nothing is read from .env
+    TestBotIdentity::default().id()
+}
+
+pub fn make_bot_string() -> String {
+    serde_json::to_string(&TestBotIdentity::default().me()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::{text, State};
+    use teloxide::prelude::*;
+
+    #[tokio::test]
+    async fn test_a_custom_bot_identity() {
+        let ctx = TestContext::with_identity(
+            FakeBot::default()
+                .token("42:ANOTHER_TOKEN")
+                .username("calculator_bot"),
+        );
+
+        // The fake responses have this bot, not the default one
+        let me = ctx.bot.get_me().await.unwrap();
+        assert_eq!(me.id, UserId(42));
+        assert_eq!(me.username(), "calculator_bot");
+        let sent = ctx
+            .bot
+            .send_message(ChatId(ctx.user_id), "Hi")
+            .await
+            .unwrap();
+        assert_eq!(sent.from().unwrap().id, UserId(42));
+
+        // And the commands are parsed with its username
+        let message = ctx.make_message("/start@calculator_bot", true, ChatType::Group);
+        ctx.run_update(make_message_update(message), State::Start)
+            .await
+            .unwrap();
+        assert_eq!(ctx.get_sent_texts().last().unwrap(), text::WHAT_DO_YOU_WANT);
+    }
+}
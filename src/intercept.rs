@@ -1,147 +1,1294 @@
-use std::{ops::Deref, sync::Mutex};
-
-use teloxide::{
-    payloads::{
-        DeleteMessage, EditMessageReplyMarkup, EditMessageText, EditMessageTextSetters,
-        PinChatMessage, SendMessage, SendMessageSetters,
-    },
-    requests::{JsonRequest, Request},
-    types::{InlineKeyboardMarkup, Message, ParseMode, ReplyMarkup},
-    RequestError,
-};
-
-use crate::test_utils::{
-    escape_control_characters, make_bot_string, TEST_USER_FIRST_NAME, TEST_USER_LAST_NAME,
-    TEST_USER_NAME,
-};
-
-pub static LAST_SENT_MESSAGE: Mutex<Option<Message>> = Mutex::new(None);
-// Mutex allows us to have a global variable that you can modify. It restricts the code to only one
-// access at a time, to avoid race conditions. To use that mutex, you need to call lock().unwrap()
-// If the code panics out while using the lock, you will get a PoisonError if you try to access it again
+use std::sync::{Arc, Mutex};
 
-pub fn make_bot_message(
-    text: &str,
-    chat_id: &str,
-    reply_markup: Option<InlineKeyboardMarkup>,
-) -> Message {
-    // Again, very very ugly, but it works
-    let message_str = format!(
-        r#"{{"message_id":{message_id},"message_thread_id":null,"date":1234567890,"chat":{{"id":{chat_id},"type":"private","username":"{username}","first_name":"{first_name}","last_name":"{last_name}","bio":null,"has_private_forwards":null,"has_restricted_voice_and_video_messages":null,"emoji_status_custom_emoji_id":null}},"via_bot":null,"from":{bot},"text":"{message_text}","entities":[], "reply_markup":{reply_markup}, "is_topic_message":false,"is_automatic_forward":false,"has_protected_content":false}}"#,
-        message_id = 1,
-        username = TEST_USER_NAME,
-        first_name = TEST_USER_FIRST_NAME,
-        last_name = TEST_USER_LAST_NAME,
-        chat_id = chat_id,
-        message_text = escape_control_characters(text),
-        reply_markup = serde_json::to_string(&reply_markup).unwrap(),
-        bot = make_bot_string()
-    );
-    // Maybe you need to make a different case for group/supergroup/gigagroup/channel chats, but i don't think there is a
-    // lot of uses for the chat field on already sent message
-    let message: Message = serde_json::from_str(&message_str).unwrap();
-    message
-}
-
-pub trait TestingInterceptAndReturnMessage {
-    // This trait just
-    // 1. Intercepts the request if it is a test environment, and "sends" it, returning a message
-    //    that would've been returned by telegram, without actually sending it to telegram
-    // 2. Saves the last sent message in LAST_SENT_MESSAGE for testing purposes
-    // !!! IT HAS TO BE THE LAST TRAIT THAT IS CALLED !!!
-    async fn intercept(self) -> Result<Message, RequestError>;
-}
-
-pub trait TestingIntercept {
-    // And this is the "lazy" version of the upper trait, it doesn't return anything, so it is
-    // easier to write, but you can't test the output of such requests. In a perfect world this
-    // trait would be gone, but im too lazy to implement a fake message for every possible request.
-    // If you want - you can contribute your implementation
-    // !!! IT HAS TO BE THE LAST TRAIT THAT IS CALLED !!!
-    async fn intercept(self) -> Result<(), RequestError>;
-}
-
-impl TestingInterceptAndReturnMessage for JsonRequest<SendMessage> {
-    async fn intercept(self) -> Result<Message, RequestError> {
-        if cfg!(test) {
-            let req = self.deref(); // Get an actual request object
-
-            let reply_markup = match req.reply_markup.clone() {
-                // Since we are trying to get a
-                // message, we need only inline keyboard, as others aren't shown in a message
-                // return type
-                Some(keyboard) => match keyboard {
-                    ReplyMarkup::InlineKeyboard(inline_keyboard) => Some(inline_keyboard),
-                    _ => None,
-                },
-                None => None,
-            };
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use teloxide::{payloads::*, requests::Payload, types::*, RequestError};
 
-            let message = make_bot_message(
-                req.text.as_str(),
-                &req.chat_id.to_string().as_str(),
-                reply_markup,
-            );
+#[cfg(test)]
+use crate::failures::take_failure;
+use crate::identity::{get_bot_id, make_bot_string};
+use crate::test_bot::TestBot;
 
-            *LAST_SENT_MESSAGE.lock().unwrap() = Some(message.clone());
+/*
+    Constants
+
+    Who the fake responses are about, when the request doesn't say. The real bot makes fake
+    responses too (in dry run), so they are here, and not in test_utils.rs, that is only for tests
+*/
+pub const TEST_USER_ID: i64 = 123456789;
+pub const TEST_GROUP_ID: i64 = -1000123456789; // "-100" + the id, like telegram's supergroups
+pub const TEST_USER_NAME: &str = "test_user";
+pub const TEST_USER_FIRST_NAME: &str = "Test";
+pub const TEST_USER_LAST_NAME: &str = "User";
+pub const TEST_DATE: i64 = 1234567890; // The date of every fake message, if you don't set one
+
+pub type Journal = Arc<Mutex<Vec<SentRequest>>>;
+// Every request that was intercepted, in the order they were sent. Every TestBot has its own
+// journal, so the tests don't see each other's requests and can run at the same time.
+// Arc lets all the clones of the bot share the same journal, and Mutex allows to modify it from
+// any of them. It restricts the code to only one access at a time, to avoid race conditions. To
+// use that mutex, you need to call lock().unwrap()
+// If the code panics out while using the lock, you will get a PoisonError if you try to access it again
+
+#[derive(Clone, Debug)]
+#[allow(dead_code)] // The fields are read only in tests
+pub struct SentRequest {
+    pub method: &'static str, // The name of the request, e.g. "SendMessage"
+    pub chat_id: Option<Recipient>, // Not every request is sent to a chat
+    pub payload: SentPayload, // The request itself, if you need to check some specific field
+    pub json: serde_json::Value, // The same request, but as telegram would've seen it
+    pub message_id: Option<MessageId>, // The message it sent or edited, see history.rs
+}
 
-            return Ok(message);
+impl SentRequest {
+    pub fn new<P: InterceptPayload>(payload: P) -> Self {
+        let json = serde_json::to_value(&payload).unwrap();
+        // Every request names the chat field the same way, so we don't need to match on the
+        // request type to get it
+        let chat_id = json
+            .get("chat_id")
+            .and_then(|chat_id| serde_json::from_value(chat_id.clone()).ok());
+        // The id of an edited message is in the request. New messages get their ids from the
+        // fake history, when the request is intercepted
+        let message_id = match edits_a_message(P::NAME) && chat_id.is_some() {
+            // MessageId is flattened in the requests, so it's just a number here
+            true => json["message_id"].as_i64().map(|id| MessageId(id as i32)),
+            false => None,
+        };
+        Self {
+            method: P::NAME,
+            chat_id,
+            payload: payload.into(),
+            json,
+            message_id,
         }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        // Only messages and text edits have it
+        self.json.get("text")?.as_str()
+    }
+
+    pub fn inline_keyboard(&self) -> Option<InlineKeyboardMarkup> {
+        // The inline keyboard of the message, if it has one. Reply keyboards don't count
+        serde_json::from_value(self.json.get("reply_markup")?.clone()).ok()
+    }
 
-        self.parse_mode(ParseMode::Html).send().await
+    pub fn thread_id(&self) -> Option<i32> {
+        // The forum topic the request was sent into, None is "General" or not a forum at all
+        self.json
+            .get("message_thread_id")?
+            .as_i64()
+            .map(|id| id as i32)
     }
 }
 
-impl TestingInterceptAndReturnMessage for JsonRequest<EditMessageText> {
-    async fn intercept(self) -> Result<Message, RequestError> {
-        if cfg!(test) {
-            let req = self.deref();
-            let message = make_bot_message(
-                req.text.as_str(),
-                &req.chat_id.to_string().as_str(),
-                req.reply_markup.clone(),
-            );
+pub fn edits_a_message(method: &str) -> bool {
+    // The requests that change a message that is already in the chat
+    method.starts_with("EditMessage")
+        || matches!(
+            method,
+            "StopMessageLiveLocation" | "StopPoll" | "SetGameScore"
+        )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterceptMode {
+    Send,      // Nothing is intercepted, it works like a normal `Bot`
+    DryRun,    // The bot gets real updates, but everything it would've sent is only logged
+    Intercept, // Nothing is sent to telegram at all, that's what the tests use
+}
+
+impl InterceptMode {
+    pub fn from_env() -> Self {
+        // Set BOT_DRY_RUN=true in .env to run the bot in dry run mode
+        match dotenvy::var("BOT_DRY_RUN").as_deref() {
+            Ok("true") | Ok("1") => InterceptMode::DryRun,
+            _ => InterceptMode::Send,
+        }
+    }
 
-            *LAST_SENT_MESSAGE.lock().unwrap() = Some(message.clone());
-            return Ok(message);
+    pub fn should_intercept<P: InterceptPayload>(self) -> bool {
+        match self {
+            InterceptMode::Send => false,
+            // In dry run we still need to get the updates and other info from telegram, we just
+            // shouldn't change anything there
+            InterceptMode::DryRun => !P::READ_ONLY,
+            InterceptMode::Intercept => true,
         }
-        self.parse_mode(ParseMode::Html).send().await
     }
 }
 
+pub fn intercept<P: InterceptPayload>(
+    payload: &P,
+    bot: &TestBot,
+) -> Result<P::Output, RequestError> {
+    // Every request that TestBot intercepts ends up here, instead of going to telegram
+    let mut request = SentRequest::new(payload.clone());
+    if bot.mode() == InterceptMode::DryRun {
+        // The bot can run for days in dry run, so we don't keep the requests or the history, we
+        // just log them
+        log::info!("Dry run, not sending {}: {}", request.method, request.json);
+        return payload.fake_response();
+    }
+    #[cfg(test)]
+    let failure = take_failure(bot.failures(), &request);
+    #[cfg(not(test))]
+    let failure = None; // Only the tests can make the requests fail, see failures.rs
+    let response = match failure {
+        Some(error) => Err(error), // The test asked for it to fail
+        None => payload
+            .fake_response()
+            .map(|response| bot.identity().replace_the_default_bot(response))
+            .and_then(|response| bot.history().lock().unwrap().apply(&mut request, response)),
+    };
+    bot.journal().lock().unwrap().push(request); // Even if it failed, the bot did send it
+    response
+}
+
+pub trait InterceptPayload:
+    Payload<Output: Serialize + DeserializeOwned> + Clone + Serialize + Into<SentPayload>
+{
+    // This trait makes up the response that telegram would've returned for the request, without
+    // actually sending it to telegram. Every request type (payload) has to implement it, because
+    // `TestBot` has to be able to send any request.
+
+    // Requests that don't change anything in telegram, they are sent for real in dry run
+    const READ_ONLY: bool = false;
+
+    fn fake_response(&self) -> Result<Self::Output, RequestError>;
+}
+
 /*
-    If you want to make requests into ones that actually return messages,
-    follow the same steps as in test_utils.rs, just print not the user message, but the bot one.
-    If you want to implement just a TestingIntercept (so no output), use the examples below,
-    just replace the JsonRequest into the needed type
+    Helpers to make up the responses
+
+    Everything is made as a json, exactly like telegram would've sent it, and then parsed the same
+    way teloxide parses real responses. So if teloxide can't parse what telegram returns for some
+    request, the test will fail the same way the real bot would.
 */
 
-impl TestingIntercept for JsonRequest<DeleteMessage> {
-    async fn intercept(self) -> Result<(), RequestError> {
-        if cfg!(test) {
-            return Ok(());
+fn telegram_response<T: DeserializeOwned>(json: Value) -> Result<T, RequestError> {
+    serde_json::from_value(json.clone()).map_err(|source| RequestError::InvalidJson {
+        source,
+        raw: json.to_string().into(),
+    })
+}
+
+fn request_json<P: Serialize>(payload: &P) -> Value {
+    // Working with a json of the request lets us get the common fields (chat_id, reply_markup,
+    // caption, etc) the same way for every request type
+    serde_json::to_value(payload).unwrap()
+}
+
+pub fn merge(mut base: Value, fields: Value) -> Value {
+    // Adds the fields to the base object, skipping the nulls, because telegram doesn't send them
+    if let (Some(base), Value::Object(fields)) = (base.as_object_mut(), fields) {
+        for (key, value) in fields {
+            if !value.is_null() {
+                base.insert(key, value);
+            }
         }
-        self.send().await?;
-        Ok(())
     }
+    base
 }
 
-impl TestingIntercept for JsonRequest<EditMessageReplyMarkup> {
-    async fn intercept(self) -> Result<(), RequestError> {
-        if cfg!(test) {
-            return Ok(());
-        }
-        self.send().await?;
-        Ok(())
+pub fn make_bot_json() -> Value {
+    serde_json::from_str(&make_bot_string()).unwrap()
+}
+
+fn make_user_json(user_id: &Value) -> Value {
+    // If the request is about the bot itself, return the bot, otherwise it's the test user
+    if user_id.as_i64() == Some(get_bot_id()) {
+        return make_bot_json();
+    }
+    json!({
+        "id": user_id,
+        "is_bot": false,
+        "first_name": TEST_USER_FIRST_NAME,
+        "last_name": TEST_USER_LAST_NAME,
+        "username": TEST_USER_NAME,
+        "language_code": "en"
+    })
+}
+
+pub fn make_chat_json(chat_id: &Value) -> Value {
+    if let Some(username) = chat_id.as_str() {
+        // Only channels can be targeted by their username
+        return json!({
+            "id": TEST_GROUP_ID,
+            "type": "channel",
+            "title": "Test Channel",
+            "username": username.trim_start_matches('@')
+        });
+    }
+    let id = chat_id.as_i64().unwrap_or(TEST_USER_ID);
+    if id > 0 {
+        json!({
+            "id": id,
+            "type": "private",
+            "username": TEST_USER_NAME,
+            "first_name": TEST_USER_FIRST_NAME,
+            "last_name": TEST_USER_LAST_NAME
+        })
+    } else if id.to_string().starts_with("-100") {
+        // That's how telegram marks supergroup (and channel) ids
+        json!({ "id": id, "type": "supergroup", "title": "Test Group" })
+    } else {
+        json!({ "id": id, "type": "group", "title": "Test Group" })
+    }
+}
+
+pub fn make_file_json(file: &Value, fields: Value) -> Value {
+    // InputFile is serialized as a file id, an url, or "attach://..." if it is uploaded. If it's
+    // a file id, telegram returns the same one, otherwise a new one
+    let file_id = match file.as_str() {
+        Some(file_id) if !file_id.contains("://") => file_id,
+        _ => "fake_file_id",
+    };
+    merge(
+        json!({ "file_id": file_id, "file_unique_id": file_id, "file_size": 0 }),
+        fields,
+    )
+}
+
+fn make_media_json(media: &Value) -> Value {
+    // Message content for an InputMedia (used in media groups and media edits)
+    let file = &media["media"];
+    let content = match media["type"].as_str() {
+        Some("photo") => json!({
+            "photo": [make_file_json(file, json!({ "width": 90, "height": 90 }))]
+        }),
+        Some("video") => json!({
+            "video": make_file_json(file, json!({
+                "width": media.get("width").unwrap_or(&json!(1280)),
+                "height": media.get("height").unwrap_or(&json!(720)),
+                "duration": media.get("duration").unwrap_or(&json!(0)),
+                "mime_type": "video/mp4"
+            }))
+        }),
+        Some("animation") => json!({
+            "animation": make_file_json(file, json!({
+                "width": media.get("width").unwrap_or(&json!(320)),
+                "height": media.get("height").unwrap_or(&json!(240)),
+                "duration": media.get("duration").unwrap_or(&json!(0)),
+                "mime_type": "video/mp4"
+            }))
+        }),
+        Some("audio") => json!({
+            "audio": make_file_json(file, json!({
+                "duration": media.get("duration").unwrap_or(&json!(0)),
+                "performer": media.get("performer"),
+                "title": media.get("title"),
+                "mime_type": "audio/mpeg"
+            }))
+        }),
+        _ => json!({ "document": make_file_json(file, json!({})) }),
+    };
+    merge(
+        content,
+        json!({
+            "caption": media.get("caption"),
+            "caption_entities": media.get("caption_entities")
+        }),
+    )
+}
+
+pub fn make_sent_message_json(request: &Value, content: Value) -> Value {
+    // A message from the bot in the chat of the request. Edits keep the id of the edited message,
+    // new messages get id 1 here, and then the real one from the history (see history.rs)
+    // Only inline keyboards are shown in a message
+    let reply_markup = request
+        .get("reply_markup")
+        .filter(|markup| markup.get("inline_keyboard").is_some());
+    let message = json!({
+        "message_id": request.get("message_id").unwrap_or(&json!(1)),
+        "message_thread_id": request.get("message_thread_id"),
+        "date": TEST_DATE,
+        "chat": make_chat_json(&request["chat_id"]),
+        "from": make_bot_json(),
+        "caption": request.get("caption"),
+        "caption_entities": request.get("caption_entities"),
+        "reply_markup": reply_markup,
+        "is_topic_message": request.get("message_thread_id").is_some(),
+        "is_automatic_forward": false,
+        "has_protected_content": request.get("protect_content").unwrap_or(&json!(false))
+    });
+    merge(merge(json!({}), message), content)
+}
+
+fn sent_message<P: Serialize>(payload: &P, content: Value) -> Result<Message, RequestError> {
+    telegram_response(make_sent_message_json(&request_json(payload), content))
+}
+
+fn inline_message_edit<T: DeserializeOwned>() -> Result<T, RequestError> {
+    // Edits of inline messages return just `true`, because the bot doesn't see the message.
+    // For some of them teloxide expects a Message, so it will fail the same way the real bot does
+    telegram_response(json!(true))
+}
+
+/*
+    Requests that send or edit messages
+*/
+
+impl InterceptPayload for SendMessage {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        // Telegram would've parsed the html in the text, but we just leave it as it is
+        sent_message(
+            self,
+            json!({ "text": self.text, "entities": self.entities.clone().unwrap_or_default() }),
+        )
+    }
+}
+
+impl InterceptPayload for EditMessageText {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        sent_message(
+            self,
+            json!({ "text": self.text, "entities": self.entities.clone().unwrap_or_default() }),
+        )
+    }
+}
+
+impl InterceptPayload for EditMessageReplyMarkup {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        // The edited message with the new keyboard. We don't know what else was in that message,
+        // so it's just an empty text
+        sent_message(self, json!({ "text": "", "entities": [] }))
+    }
+}
+
+impl InterceptPayload for EditMessageCaption {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        // Same here, only media has captions, so we pretend that it was a photo
+        sent_message(
+            self,
+            json!({ "photo": [make_file_json(&Value::Null, json!({ "width": 90, "height": 90 }))] }),
+        )
+    }
+}
+
+impl InterceptPayload for EditMessageMedia {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(self, make_media_json(&request["media"]))
+    }
+}
+
+impl InterceptPayload for EditMessageLiveLocation {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        sent_message(
+            self,
+            json!({ "location": {
+                "latitude": self.latitude,
+                "longitude": self.longitude,
+                "horizontal_accuracy": self.horizontal_accuracy,
+                "heading": self.heading,
+                "proximity_alert_radius": self.proximity_alert_radius
+            }}),
+        )
+    }
+}
+
+impl InterceptPayload for StopMessageLiveLocation {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        sent_message(
+            self,
+            json!({ "location": { "latitude": self.latitude, "longitude": self.longitude } }),
+        )
+    }
+}
+
+impl InterceptPayload for EditMessageLiveLocationInline {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        inline_message_edit()
+    }
+}
+
+impl InterceptPayload for StopMessageLiveLocationInline {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        inline_message_edit()
+    }
+}
+
+impl InterceptPayload for SetGameScoreInline {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        inline_message_edit()
+    }
+}
+
+impl InterceptPayload for ForwardMessage {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        // We don't know what was in the forwarded message, so it's an empty text
+        let request = request_json(self);
+        let from_chat = &request["from_chat_id"];
+        let forward = if from_chat.as_i64().is_some_and(|id| id > 0) {
+            json!({ "forward_from": make_user_json(from_chat) })
+        } else {
+            json!({
+                "forward_from_chat": make_chat_json(from_chat),
+                "forward_from_message_id": request["message_id"]
+            })
+        };
+        let content = merge(
+            json!({ "text": "", "entities": [], "forward_date": TEST_DATE }),
+            forward,
+        );
+        // The forwarded message is a new message, so it doesn't keep the id of the original
+        let request = merge(request, json!({ "message_id": 1 }));
+        telegram_response(make_sent_message_json(&request, content))
+    }
+}
+
+impl InterceptPayload for CopyMessage {
+    fn fake_response(&self) -> Result<MessageId, RequestError> {
+        telegram_response(json!({ "message_id": 1 }))
+    }
+}
+
+impl InterceptPayload for SendPhoto {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(
+            self,
+            json!({
+                "photo": [make_file_json(&request["photo"], json!({ "width": 90, "height": 90 }))],
+                "has_media_spoiler": self.has_spoiler
+            }),
+        )
+    }
+}
+
+impl InterceptPayload for SendAudio {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(
+            self,
+            json!({ "audio": make_file_json(&request["audio"], json!({
+                "duration": self.duration.unwrap_or(0),
+                "performer": self.performer,
+                "title": self.title,
+                "mime_type": "audio/mpeg"
+            }))}),
+        )
+    }
+}
+
+impl InterceptPayload for SendDocument {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(
+            self,
+            json!({ "document": make_file_json(&request["document"], json!({})) }),
+        )
+    }
+}
+
+impl InterceptPayload for SendVideo {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(
+            self,
+            json!({
+                "video": make_file_json(&request["video"], json!({
+                    "width": self.width.unwrap_or(1280),
+                    "height": self.height.unwrap_or(720),
+                    "duration": self.duration.unwrap_or(0),
+                    "mime_type": "video/mp4"
+                })),
+                "has_media_spoiler": self.has_spoiler
+            }),
+        )
+    }
+}
+
+impl InterceptPayload for SendAnimation {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(
+            self,
+            json!({
+                "animation": make_file_json(&request["animation"], json!({
+                    "width": self.width.unwrap_or(320),
+                    "height": self.height.unwrap_or(240),
+                    "duration": self.duration.unwrap_or(0),
+                    "mime_type": "video/mp4" // Telegram converts gifs into mp4
+                })),
+                "has_media_spoiler": self.has_spoiler
+            }),
+        )
+    }
+}
+
+impl InterceptPayload for SendVoice {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(
+            self,
+            json!({ "voice": make_file_json(&request["voice"], json!({
+                "duration": self.duration.unwrap_or(0),
+                "mime_type": "audio/ogg"
+            }))}),
+        )
+    }
+}
+
+impl InterceptPayload for SendVideoNote {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(
+            self,
+            json!({ "video_note": make_file_json(&request["video_note"], json!({
+                "length": self.length.unwrap_or(240),
+                "duration": self.duration.unwrap_or(0)
+            }))}),
+        )
+    }
+}
+
+impl InterceptPayload for SendSticker {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(
+            self,
+            json!({ "sticker": make_file_json(&request["sticker"], json!({
+                "width": 512,
+                "height": 512,
+                "type": "regular",
+                "is_animated": false,
+                "is_video": false
+            }))}),
+        )
+    }
+}
+
+impl InterceptPayload for SendMediaGroup {
+    fn fake_response(&self) -> Result<Vec<Message>, RequestError> {
+        // Every media is a separate message, with the same media_group_id
+        let request = request_json(self);
+        let messages = request["media"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(i, media)| {
+                let content = merge(make_media_json(media), json!({ "media_group_id": "1" }));
+                let request = merge(request.clone(), json!({ "message_id": i + 1 }));
+                make_sent_message_json(&request, content)
+            })
+            .collect();
+        telegram_response(Value::Array(messages))
+    }
+}
+
+impl InterceptPayload for SendLocation {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        sent_message(
+            self,
+            json!({ "location": {
+                "latitude": self.latitude,
+                "longitude": self.longitude,
+                "horizontal_accuracy": self.horizontal_accuracy,
+                "live_period": self.live_period,
+                "heading": self.heading,
+                "proximity_alert_radius": self.proximity_alert_radius
+            }}),
+        )
+    }
+}
+
+impl InterceptPayload for SendVenue {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let location = json!({ "latitude": self.latitude, "longitude": self.longitude });
+        sent_message(
+            self,
+            json!({
+                "venue": {
+                    "location": location,
+                    "title": self.title,
+                    "address": self.address,
+                    "foursquare_id": self.foursquare_id,
+                    "foursquare_type": self.foursquare_type,
+                    "google_place_id": self.google_place_id,
+                    "google_place_type": self.google_place_type
+                },
+                "location": location
+            }),
+        )
+    }
+}
+
+impl InterceptPayload for SendContact {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        sent_message(
+            self,
+            json!({ "contact": {
+                "phone_number": self.phone_number,
+                "first_name": self.first_name,
+                "last_name": self.last_name,
+                "vcard": self.vcard
+            }}),
+        )
+    }
+}
+
+impl InterceptPayload for SendDice {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        sent_message(
+            self,
+            json!({ "dice": {
+                "emoji": request.get("emoji").unwrap_or(&json!("🎲")),
+                "value": 1 // Not very random, but tests need to be predictable
+            }}),
+        )
+    }
+}
+
+impl InterceptPayload for SendPoll {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let request = request_json(self);
+        let options: Vec<Value> = self
+            .options
+            .iter()
+            .map(|option| json!({ "text": option, "voter_count": 0 }))
+            .collect();
+        let poll = merge(
+            json!({
+                "id": "1",
+                "question": self.question,
+                "options": options,
+                "total_voter_count": 0,
+                "is_closed": self.is_closed.unwrap_or(false),
+                "is_anonymous": self.is_anonymous.unwrap_or(true),
+                "type": request.get("type").unwrap_or(&json!("regular")),
+                "allows_multiple_answers": self.allows_multiple_answers.unwrap_or(false)
+            }),
+            json!({
+                "correct_option_id": self.correct_option_id,
+                "explanation": self.explanation,
+                "explanation_entities": self.explanation_entities,
+                "open_period": self.open_period,
+                "close_date": request.get("close_date")
+            }),
+        );
+        sent_message(self, json!({ "poll": poll }))
+    }
+}
+
+impl InterceptPayload for SendInvoice {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        let total_amount: i64 = self.prices.iter().map(|price| price.amount as i64).sum();
+        sent_message(
+            self,
+            json!({ "invoice": {
+                "title": self.title,
+                "description": self.description,
+                "start_parameter": self.start_parameter.clone().unwrap_or_default(),
+                "currency": self.currency,
+                "total_amount": total_amount
+            }}),
+        )
     }
 }
 
-impl TestingIntercept for JsonRequest<PinChatMessage> {
-    async fn intercept(self) -> Result<(), RequestError> {
-        if cfg!(test) {
-            return Ok(());
+impl InterceptPayload for SendGame {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        sent_message(
+            self,
+            json!({ "game": { "title": self.game_short_name, "description": "", "photo": [] } }),
+        )
+    }
+}
+
+impl InterceptPayload for SetGameScore {
+    fn fake_response(&self) -> Result<Message, RequestError> {
+        // We don't know the game, only the message with it
+        sent_message(
+            self,
+            json!({ "game": { "title": "", "description": "", "photo": [] } }),
+        )
+    }
+}
+
+impl InterceptPayload for StopPoll {
+    fn fake_response(&self) -> Result<Poll, RequestError> {
+        // We don't know the question and the options of the stopped poll
+        telegram_response(json!({
+            "id": "1",
+            "question": "",
+            "options": [],
+            "total_voter_count": 0,
+            "is_closed": true,
+            "is_anonymous": true,
+            "type": "regular",
+            "allows_multiple_answers": false
+        }))
+    }
+}
+
+/*
+    Requests that get something from telegram
+*/
+
+impl InterceptPayload for GetMe {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<Me, RequestError> {
+        telegram_response(make_bot_json())
+    }
+}
+
+impl InterceptPayload for GetChat {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<Chat, RequestError> {
+        telegram_response(make_chat_json(&request_json(self)["chat_id"]))
+    }
+}
+
+impl InterceptPayload for GetChatMember {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<ChatMember, RequestError> {
+        let request = request_json(self);
+        telegram_response(json!({
+            "status": "member",
+            "user": make_user_json(&request["user_id"])
+        }))
+    }
+}
+
+impl InterceptPayload for GetChatAdministrators {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<Vec<ChatMember>, RequestError> {
+        // The test user is the owner of every chat
+        telegram_response(json!([{
+            "status": "creator",
+            "user": make_user_json(&json!(TEST_USER_ID)),
+            "is_anonymous": false
+        }]))
+    }
+}
+
+impl InterceptPayload for GetChatMemberCount {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<u32, RequestError> {
+        telegram_response(json!(2)) // The test user and the bot
+    }
+}
+
+impl InterceptPayload for GetChatMembersCount {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<u32, RequestError> {
+        telegram_response(json!(2))
+    }
+}
+
+impl InterceptPayload for GetFile {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<File, RequestError> {
+        telegram_response(make_file_json(
+            &json!(self.file_id),
+            json!({ "file_path": format!("files/{}", self.file_id) }),
+        ))
+    }
+}
+
+impl InterceptPayload for GetUserProfilePhotos {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<UserProfilePhotos, RequestError> {
+        telegram_response(json!({ "total_count": 0, "photos": [] }))
+    }
+}
+
+impl InterceptPayload for GetMyCommands {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<Vec<BotCommand>, RequestError> {
+        telegram_response(json!([]))
+    }
+}
+
+impl InterceptPayload for GetChatMenuButton {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<MenuButton, RequestError> {
+        telegram_response(json!({ "type": "default" }))
+    }
+}
+
+impl InterceptPayload for GetMyDefaultAdministratorRights {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<ChatAdministratorRights, RequestError> {
+        telegram_response(json!({
+            "is_anonymous": false,
+            "can_manage_chat": false,
+            "can_delete_messages": false,
+            "can_manage_video_chats": false,
+            "can_restrict_members": false,
+            "can_promote_members": false,
+            "can_change_info": false,
+            "can_invite_users": false
+        }))
+    }
+}
+
+impl InterceptPayload for GetStickerSet {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<StickerSet, RequestError> {
+        telegram_response(json!({
+            "name": self.name,
+            "title": self.name,
+            "sticker_type": "regular",
+            "is_animated": false,
+            "is_video": false,
+            "stickers": []
+        }))
+    }
+}
+
+impl InterceptPayload for GetCustomEmojiStickers {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<Vec<Sticker>, RequestError> {
+        telegram_response(json!([]))
+    }
+}
+
+impl InterceptPayload for GetForumTopicIconStickers {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<Vec<Sticker>, RequestError> {
+        telegram_response(json!([]))
+    }
+}
+
+impl InterceptPayload for GetUpdates {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<Vec<Update>, RequestError> {
+        telegram_response(json!([])) // Tests give the updates to the bot themselves
+    }
+}
+
+impl InterceptPayload for GetWebhookInfo {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<WebhookInfo, RequestError> {
+        telegram_response(json!({
+            "url": "",
+            "has_custom_certificate": false,
+            "pending_update_count": 0
+        }))
+    }
+}
+
+impl InterceptPayload for GetGameHighScores {
+    const READ_ONLY: bool = true;
+
+    fn fake_response(&self) -> Result<True, RequestError> {
+        // Telegram returns a list of scores here, but teloxide expects `true`, so this request
+        // fails even with the real bot
+        telegram_response(json!([]))
+    }
+}
+
+/*
+    Requests that create something
+*/
+
+impl InterceptPayload for CreateChatInviteLink {
+    fn fake_response(&self) -> Result<ChatInviteLink, RequestError> {
+        let request = request_json(self);
+        telegram_response(merge(
+            json!({
+                "invite_link": "https://t.me/+fake_invite_link",
+                "creator": make_bot_json(),
+                "creates_join_request": self.creates_join_request.unwrap_or(false),
+                "is_primary": false,
+                "is_revoked": false
+            }),
+            json!({
+                "name": self.name,
+                "expire_date": request.get("expire_date"),
+                "member_limit": self.member_limit
+            }),
+        ))
+    }
+}
+
+impl InterceptPayload for ExportChatInviteLink {
+    fn fake_response(&self) -> Result<String, RequestError> {
+        telegram_response(json!("https://t.me/+fake_invite_link"))
+    }
+}
+
+impl InterceptPayload for CreateInvoiceLink {
+    fn fake_response(&self) -> Result<String, RequestError> {
+        telegram_response(json!("https://t.me/$fake_invoice_link"))
+    }
+}
+
+impl InterceptPayload for CreateForumTopic {
+    fn fake_response(&self) -> Result<ForumTopic, RequestError> {
+        telegram_response(json!({
+            "message_thread_id": 1,
+            "name": self.name,
+            "icon_color": self.icon_color,
+            "icon_custom_emoji_id": self.icon_custom_emoji_id
+        }))
+    }
+}
+
+impl InterceptPayload for UploadStickerFile {
+    fn fake_response(&self) -> Result<FileMeta, RequestError> {
+        telegram_response(make_file_json(&Value::Null, json!({})))
+    }
+}
+
+impl InterceptPayload for AnswerWebAppQuery {
+    fn fake_response(&self) -> Result<SentWebAppMessage, RequestError> {
+        telegram_response(json!({ "inline_message_id": "1" }))
+    }
+}
+
+// Telegram returns the edited invite link for these two, and `true` for the last one, but
+// teloxide expects a string, so they fail even with the real bot
+impl InterceptPayload for EditChatInviteLink {
+    fn fake_response(&self) -> Result<String, RequestError> {
+        telegram_response(json!({
+            "invite_link": self.invite_link,
+            "creator": make_bot_json(),
+            "creates_join_request": self.creates_join_request.unwrap_or(false),
+            "is_primary": false,
+            "is_revoked": false
+        }))
+    }
+}
+
+impl InterceptPayload for RevokeChatInviteLink {
+    fn fake_response(&self) -> Result<String, RequestError> {
+        telegram_response(json!({
+            "invite_link": self.invite_link,
+            "creator": make_bot_json(),
+            "creates_join_request": false,
+            "is_primary": false,
+            "is_revoked": true
+        }))
+    }
+}
+
+impl InterceptPayload for DeleteChatPhoto {
+    fn fake_response(&self) -> Result<String, RequestError> {
+        telegram_response(json!(true))
+    }
+}
+
+/*
+    And the rest of the requests just return `true` if they succeed
+*/
+
+macro_rules! returns_true {
+    ($($payload:ty),* $(,)?) => {
+        // The names of these requests, so the fake server (see fake_server.rs) knows which ones
+        // it can answer with `true`
+        #[cfg(test)]
+        pub const RETURNS_TRUE: &[&str] = &[$(<$payload as Payload>::NAME),*];
+
+        $(
+            impl InterceptPayload for $payload {
+                fn fake_response(&self) -> Result<True, RequestError> {
+                    Ok(True)
+                }
+            }
+        )*
+    };
+}
+
+returns_true![
+    SetWebhook,
+    DeleteWebhook,
+    LogOut,
+    Close,
+    SendChatAction,
+    BanChatMember,
+    KickChatMember,
+    UnbanChatMember,
+    RestrictChatMember,
+    PromoteChatMember,
+    SetChatAdministratorCustomTitle,
+    BanChatSenderChat,
+    UnbanChatSenderChat,
+    SetChatPermissions,
+    ApproveChatJoinRequest,
+    DeclineChatJoinRequest,
+    SetChatPhoto,
+    SetChatTitle,
+    SetChatDescription,
+    PinChatMessage,
+    UnpinChatMessage,
+    UnpinAllChatMessages,
+    LeaveChat,
+    SetChatStickerSet,
+    DeleteChatStickerSet,
+    EditForumTopic,
+    CloseForumTopic,
+    ReopenForumTopic,
+    DeleteForumTopic,
+    UnpinAllForumTopicMessages,
+    EditGeneralForumTopic,
+    CloseGeneralForumTopic,
+    ReopenGeneralForumTopic,
+    HideGeneralForumTopic,
+    UnhideGeneralForumTopic,
+    AnswerCallbackQuery,
+    SetMyCommands,
+    SetChatMenuButton,
+    SetMyDefaultAdministratorRights,
+    DeleteMyCommands,
+    AnswerInlineQuery,
+    EditMessageTextInline,
+    EditMessageCaptionInline,
+    EditMessageMediaInline,
+    EditMessageReplyMarkupInline,
+    DeleteMessage,
+    CreateNewStickerSet,
+    AddStickerToSet,
+    SetStickerPositionInSet,
+    DeleteStickerFromSet,
+    SetStickerSetThumb,
+    AnswerShippingQuery,
+    AnswerPreCheckoutQuery,
+    SetPassportDataErrors,
+];
+
+// And this makes the `SentPayload` enum, with a variant for every request type, so that you can
+// match on the request in the journal, e.g. `SentPayload::SendMessage(request)`
+macro_rules! sent_payloads {
+    ($($payload:ident),* $(,)?) => {
+        #[derive(Clone, Debug)]
+        #[allow(dead_code)] // Most of the variants are read only by the tests that need them
+        #[allow(clippy::large_enum_variant)] // It's for tests, we don't care about the size
+        pub enum SentPayload {
+            $($payload($payload),)*
         }
-        self.send().await?;
-        Ok(())
+
+        $(
+            impl From<$payload> for SentPayload {
+                fn from(payload: $payload) -> Self {
+                    SentPayload::$payload(payload)
+                }
+            }
+        )*
+    };
+}
+
+sent_payloads![
+    GetUpdates,
+    SetWebhook,
+    DeleteWebhook,
+    GetWebhookInfo,
+    GetMe,
+    LogOut,
+    Close,
+    SendMessage,
+    ForwardMessage,
+    CopyMessage,
+    SendPhoto,
+    SendAudio,
+    SendDocument,
+    SendVideo,
+    SendAnimation,
+    SendVoice,
+    SendVideoNote,
+    SendMediaGroup,
+    SendLocation,
+    EditMessageLiveLocation,
+    EditMessageLiveLocationInline,
+    StopMessageLiveLocation,
+    StopMessageLiveLocationInline,
+    SendVenue,
+    SendContact,
+    SendPoll,
+    SendDice,
+    SendChatAction,
+    GetUserProfilePhotos,
+    GetFile,
+    BanChatMember,
+    KickChatMember,
+    UnbanChatMember,
+    RestrictChatMember,
+    PromoteChatMember,
+    SetChatAdministratorCustomTitle,
+    BanChatSenderChat,
+    UnbanChatSenderChat,
+    SetChatPermissions,
+    ExportChatInviteLink,
+    CreateChatInviteLink,
+    EditChatInviteLink,
+    RevokeChatInviteLink,
+    ApproveChatJoinRequest,
+    DeclineChatJoinRequest,
+    SetChatPhoto,
+    DeleteChatPhoto,
+    SetChatTitle,
+    SetChatDescription,
+    PinChatMessage,
+    UnpinChatMessage,
+    UnpinAllChatMessages,
+    LeaveChat,
+    GetChat,
+    GetChatAdministrators,
+    GetChatMemberCount,
+    GetChatMembersCount,
+    GetChatMember,
+    SetChatStickerSet,
+    DeleteChatStickerSet,
+    GetForumTopicIconStickers,
+    CreateForumTopic,
+    EditForumTopic,
+    CloseForumTopic,
+    ReopenForumTopic,
+    DeleteForumTopic,
+    UnpinAllForumTopicMessages,
+    EditGeneralForumTopic,
+    CloseGeneralForumTopic,
+    ReopenGeneralForumTopic,
+    HideGeneralForumTopic,
+    UnhideGeneralForumTopic,
+    AnswerCallbackQuery,
+    SetMyCommands,
+    GetMyCommands,
+    SetChatMenuButton,
+    GetChatMenuButton,
+    SetMyDefaultAdministratorRights,
+    GetMyDefaultAdministratorRights,
+    DeleteMyCommands,
+    AnswerInlineQuery,
+    AnswerWebAppQuery,
+    EditMessageText,
+    EditMessageTextInline,
+    EditMessageCaption,
+    EditMessageCaptionInline,
+    EditMessageMedia,
+    EditMessageMediaInline,
+    EditMessageReplyMarkup,
+    EditMessageReplyMarkupInline,
+    StopPoll,
+    DeleteMessage,
+    SendSticker,
+    GetStickerSet,
+    GetCustomEmojiStickers,
+    UploadStickerFile,
+    CreateNewStickerSet,
+    AddStickerToSet,
+    SetStickerPositionInSet,
+    DeleteStickerFromSet,
+    SetStickerSetThumb,
+    SendInvoice,
+    CreateInvoiceLink,
+    AnswerShippingQuery,
+    AnswerPreCheckoutQuery,
+    SetPassportDataErrors,
+    SendGame,
+    SetGameScore,
+    SetGameScoreInline,
+    GetGameHighScores,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_bot::TestBot;
+    use crate::test_utils::get_bot;
+    use teloxide::prelude::*;
+
+    // These tests send requests straight from the TestBot, without any handlers, to check that
+    // the fake responses look like the ones from telegram
+
+    #[tokio::test]
+    async fn test_edit_message_reply_markup_response() {
+        let bot = TestBot::new(get_bot());
+        // Only the messages that are in the chat can be edited, see history.rs
+        let sent = bot
+            .send_message(ChatId(TEST_USER_ID), "Hello")
+            .await
+            .unwrap();
+        let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback("Ok", "ok")]]);
+        let message = bot
+            .edit_message_reply_markup(ChatId(TEST_USER_ID), sent.id)
+            .reply_markup(keyboard.clone())
+            .await
+            .unwrap();
+
+        // The edited message is the one from the request, and it has the new keyboard
+        assert_eq!(message.id, sent.id);
+        assert_eq!(message.text(), Some("Hello"));
+        assert_eq!(message.chat.id, ChatId(TEST_USER_ID));
+        assert_eq!(message.reply_markup(), Some(&keyboard));
+    }
+
+    #[tokio::test]
+    async fn test_send_photo_response() {
+        let bot = TestBot::new(get_bot());
+        let message = bot
+            .send_photo(ChatId(TEST_GROUP_ID), InputFile::file_id("photo_file_id"))
+            .caption("A photo")
+            .await
+            .unwrap();
+
+        assert_eq!(message.photo().unwrap()[0].file.id, "photo_file_id");
+        assert_eq!(message.caption(), Some("A photo"));
+        assert!(message.chat.is_supergroup());
+        assert!(message.from().unwrap().is_bot);
+    }
+
+    #[tokio::test]
+    async fn test_true_responses() {
+        let bot = TestBot::new(get_bot());
+        let sent = bot
+            .send_message(ChatId(TEST_USER_ID), "Hello")
+            .await
+            .unwrap();
+        bot.pin_chat_message(ChatId(TEST_USER_ID), sent.id)
+            .await
+            .unwrap();
+        bot.delete_message(ChatId(TEST_USER_ID), sent.id)
+            .await
+            .unwrap();
+        bot.answer_callback_query("1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        // Dry run makes up the responses too, but only logs the requests instead of keeping them
+        let bot = TestBot::with_mode(get_bot(), InterceptMode::DryRun);
+        let message = bot
+            .send_message(ChatId(TEST_USER_ID), "Hello")
+            .await
+            .unwrap();
+
+        assert_eq!(message.text(), Some("Hello"));
+        assert!(bot.journal().lock().unwrap().is_empty());
+        // And the requests that don't change anything are sent to telegram for real
+        assert!(!InterceptMode::DryRun.should_intercept::<GetMe>());
+        assert!(InterceptMode::Intercept.should_intercept::<GetMe>());
+    }
+
+    #[tokio::test]
+    async fn test_inline_edit_fails_like_in_telegram() {
+        // Telegram returns `true` for it, but teloxide expects a message
+        let bot = TestBot::new(get_bot());
+        let result = bot
+            .stop_message_live_location_inline("inline_message_id", 1.0, 2.0)
+            .await;
+        assert!(matches!(result, Err(RequestError::InvalidJson { .. })));
     }
 }
@@ -1,13 +1,43 @@
+#[macro_use]
+mod requester_forward; // Has to be the first, so the macro is visible in the other modules
+mod history;
+mod identity;
 mod intercept;
-mod test_utils; // Yes, i can just make it cfg!(test), but then the dependencies of intercept.rs
-                // will be not as compact
+mod migrations;
+mod operation;
+mod record;
+mod test_bot;
 mod text;
+
+// Only for tests, the real bot doesn't need them, and neither do their dependencies (see the
+// dev-dependencies in Cargo.toml). The modules above are in the real bot too, because it goes
+// through the TestBot, for the dry run and the recordings
+#[cfg(test)]
+mod builders;
+#[cfg(test)]
+mod failures;
+#[cfg(test)]
+mod fake_server;
+#[cfg(test)]
+mod scenario;
+#[cfg(test)]
+mod snapshot;
+#[cfg(test)]
+mod test_utils;
 use std::error::Error;
+use std::sync::Arc;
 
 use dotenvy::dotenv;
-use teloxide::dispatching::dialogue::serializer::Cbor;
 use teloxide::dispatching::dialogue::{Dialogue, ErasedStorage, RedisStorage, Storage};
+use teloxide::dispatching::DefaultKey;
 use teloxide::prelude::*;
+use teloxide::{adaptors::DefaultParseMode, types::ParseMode};
+
+use crate::intercept::InterceptMode;
+use crate::migrations::VersionedCbor;
+use crate::operation::Operation;
+use crate::record::Recorder;
+use crate::test_bot::TestBot;
 
 mod bot_schema;
 mod handlers;
@@ -20,39 +50,67 @@ pub type MyStorage = std::sync::Arc<ErasedStorage<State>>;
 pub enum State {
     #[default]
     Start, // The default state, from which you can send '/start'
-    WhatDoYouWant, // We ask, what do you want, to add or subtract
+    WhatDoYouWant, // We ask, what do you want to do with the numbers (see operation.rs)
     GetFirstNumber {
         // We got what the user wants to do, and we ask for the first number
-        operation: String,
+        operation: Operation,
     },
     GetSecondNumber {
         // Now ask for the second number
         first_number: i32,
-        operation: String,
+        operation: Operation,
     },
 }
 
+pub fn add_adaptors<B: Requester>(bot: B) -> DefaultParseMode<B> {
+    // Every adaptor that the bot uses goes here, so that the tests send exactly the same
+    // requests as the real bot does
+    bot.parse_mode(ParseMode::Html)
+}
+
 pub async fn get_bot_storage() -> MyStorage {
-    let storage: MyStorage = RedisStorage::open(dotenvy::var("REDIS_URL").unwrap(), Cbor)
+    let storage: MyStorage = RedisStorage::open(dotenvy::var("REDIS_URL").unwrap(), VersionedCbor)
         // For reasons unknown to me, Binary serializer doesn't accept json-like objects,
-        // Message in particular, so im using it. Also, i don't think that InMemStorage
-        // can work with tests, we need the storage to be persistent
+        // Message in particular, so im using Cbor, with the version of the State around it (see
+        // migrations.rs). The tests don't use redis, every TestContext in test_utils.rs has its
+        // own storage in memory, with the same serializer
         .await
         .unwrap()
         .erase();
     storage
 }
 
+pub fn make_dispatcher(
+    bot: TestBot,
+    storage: MyStorage,
+) -> Dispatcher<DefaultParseMode<TestBot>, Box<dyn Error + Send + Sync>, DefaultKey> {
+    // Everything the real bot runs, except the bot and the storage, so the tests can run the same
+    // dispatcher with a fake server (see fake_server.rs) and an in memory storage
+    let mut handler = bot_schema::schema::<DefaultParseMode<TestBot>>();
+    if let Some(recorder) = bot.recorder() {
+        // Every update is recorded before the schema gets it
+        handler = dptree::entry()
+            .inspect(move |update: Update| recorder.record_update(&update))
+            .branch(handler);
+    }
+
+    Dispatcher::builder(add_adaptors(bot), handler)
+        .dependencies(dptree::deps![storage])
+        .build()
+}
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     pretty_env_logger::init();
 
-    let bot = Bot::from_env();
+    // The real bot goes through TestBot too, so it can run in dry run mode, and record
+    let mut bot = TestBot::with_mode(Bot::from_env(), InterceptMode::from_env());
+    if let Some(recorder) = Recorder::from_env() {
+        bot = bot.with_recorder(Arc::new(recorder));
+    }
 
-    Dispatcher::builder(bot, bot_schema::schema())
-        .dependencies(dptree::deps![get_bot_storage().await])
-        .build()
+    make_dispatcher(bot, get_bot_storage().await)
         .dispatch()
         .await;
 }
@@ -0,0 +1,220 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use teloxide::dispatching::dialogue::serializer::Serializer;
+
+use crate::operation::Operation;
+use crate::State;
+
+/*
+    Versions of the State, and the migrations between them
+
+    Redis keeps the states of the users between the deploys. If a new version of the bot changes
+    `State`, the old states in redis don't fit into it anymore, and the user would have to start
+    over (see reset_the_broken_state in bot_schema.rs). So every state is saved with the version
+    of `State` it was saved by:
+
+    { "version": 2, "state": { "GetFirstNumber": { "operation": "add" } } }
+
+    and when an older one is read, it goes through the migrations, one version at a time, until it
+    looks like the current `State`. If you change `State`:
+    1) Add 1 to STATE_VERSION
+    2) Write a function that changes the old state (as json) into the new one, and add it to
+    MIGRATIONS, with the version it migrates from
+    3) Add a test with the bytes of the old state to the tests below, so it keeps working
+*/
+
+pub const STATE_VERSION: u32 = 2;
+
+pub type Migration = fn(Value) -> Result<Value, String>;
+
+pub const MIGRATIONS: &[(u32, Migration)] = &[
+    // (the version it migrates from, the migration)
+    (0, from_unversioned),
+    (1, from_v1),
+];
+
+fn from_unversioned(state: Value) -> Result<Value, String> {
+    // Before the versions, the states were saved by themselves, without the version around them.
+    // The State didn't change, only the way it is saved
+    Ok(state)
+}
+
+fn from_v1(mut state: Value) -> Result<Value, String> {
+    // The operation was any String, whatever the button sent, and now it's an Operation. The old
+    // buttons sent "add" and "subtract", but a forged button could send anything, and that can't
+    // be turned into an Operation
+    for variant in ["GetFirstNumber", "GetSecondNumber"] {
+        let Some(fields) = state.get_mut(variant) else {
+            continue;
+        };
+        let old = fields["operation"].as_str().unwrap_or_default();
+        let operation = Operation::from_data(old).ok_or(format!("unknown operation {old:?}"))?;
+        fields["operation"] = serde_json::to_value(operation).map_err(|error| error.to_string())?;
+    }
+    Ok(state)
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    Cbor(serde_cbor::Error), // The bytes aren't cbor at all
+    Json(serde_json::Error), // It's cbor, but after the migrations it still isn't a State
+    Migration(u32, String),  // The migration from this version failed
+    NoMigration(u32),        // Nobody wrote a migration from this version
+    FromTheFuture(u32),      // A newer version of the bot saved it, and then it was rolled back
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Cbor(error) => write!(f, "the state isn't valid cbor: {error}"),
+            StateError::Json(error) => write!(f, "the state doesn't fit into State: {error}"),
+            StateError::Migration(version, error) => {
+                write!(f, "the migration from version {version} failed: {error}")
+            }
+            StateError::NoMigration(version) => write!(f, "no migration from version {version}"),
+            StateError::FromTheFuture(version) => write!(
+                f,
+                "the state has version {version}, but this bot knows only up to {STATE_VERSION}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+#[derive(Serialize, Deserialize)]
+struct Versioned<T> {
+    version: u32,
+    state: T,
+}
+
+pub fn migrate(mut version: u32, mut state: Value) -> Result<Value, StateError> {
+    // Runs every migration from this version up to the current one
+    if version > STATE_VERSION {
+        return Err(StateError::FromTheFuture(version));
+    }
+    while version < STATE_VERSION {
+        let (_, migration) = MIGRATIONS
+            .iter()
+            .find(|(from, _)| *from == version)
+            .ok_or(StateError::NoMigration(version))?;
+        state = migration(state).map_err(|error| StateError::Migration(version, error))?;
+        version += 1;
+    }
+    Ok(state)
+}
+
+pub struct VersionedCbor;
+// The same cbor as teloxide's Cbor serializer, but with the version, give it to the storage
+// instead of Cbor
+
+impl Serializer<State> for VersionedCbor {
+    type Error = StateError;
+
+    fn serialize(&self, state: &State) -> Result<Vec<u8>, StateError> {
+        let versioned = Versioned {
+            version: STATE_VERSION,
+            state,
+        };
+        serde_cbor::to_vec(&versioned).map_err(StateError::Cbor)
+    }
+
+    fn deserialize(&self, data: &[u8]) -> Result<State, StateError> {
+        // Cbor is read into json, because the migrations don't know the old types, they only
+        // change the fields
+        let value: Value = serde_cbor::from_slice(data).map_err(StateError::Cbor)?;
+        let (version, state) = match serde_json::from_value::<Versioned<Value>>(value.clone()) {
+            Ok(versioned) => (versioned.version, versioned.state),
+            // A State is never an object with "version" and "state", its keys are the variants
+            Err(_) => (0, value),
+        };
+        let state = migrate(version, state)?;
+        serde_json::from_value(state).map_err(StateError::Json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::text;
+    use serde_json::json;
+
+    #[test]
+    fn test_every_old_version_has_a_migration() {
+        for version in 0..STATE_VERSION {
+            assert!(
+                MIGRATIONS.iter().any(|(from, _)| *from == version),
+                "There is no migration from version {version}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_state_from_before_the_versions() {
+        // Saved by the old bot, with the plain Cbor serializer, in the middle of the dialogue
+        let ctx = TestContext::new();
+        let old_state = json!({ "GetSecondNumber": { "first_number": 2, "operation": "add" } });
+        ctx.set_raw_state(&ctx.get_dialogue(), serde_cbor::to_vec(&old_state).unwrap());
+
+        // The user doesn't notice anything, and just finishes it
+        let message = ctx.make_message("3", false, ChatType::Private);
+        ctx.dispatch(make_message_update(message)).await.unwrap();
+        ctx.check_the_state_and_text(State::Start, &(text::YOUR_RESULT.to_owned() + "5"))
+            .await;
+
+        // And now it's saved with the version
+        let saved = ctx.get_raw_state(&ctx.get_dialogue()).unwrap();
+        let saved: Value = serde_cbor::from_slice(&saved).unwrap();
+        assert_eq!(saved, json!({ "version": STATE_VERSION, "state": "Start" }));
+    }
+
+    #[test]
+    fn test_a_state_from_version_1() {
+        // The operation was a String, whatever the button sent
+        let old_state = json!({
+            "version": 1,
+            "state": { "GetSecondNumber": { "first_number": 2, "operation": "subtract" } }
+        });
+        let state = VersionedCbor.deserialize(&serde_cbor::to_vec(&old_state).unwrap());
+        assert_eq!(
+            state.unwrap(),
+            State::GetSecondNumber {
+                first_number: 2,
+                operation: Operation::Subtract
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_an_unknown_operation_from_version_1() {
+        // A forged button saved something that was never an operation. It can't be migrated, so
+        // the user gets the apology, and starts over (see reset_the_broken_state in bot_schema.rs)
+        let ctx = TestContext::new();
+        let old_state = json!({
+            "version": 1,
+            "state": { "GetFirstNumber": { "operation": "multiply_by_ten" } }
+        });
+        let bytes = serde_cbor::to_vec(&old_state).unwrap();
+        assert!(matches!(
+            VersionedCbor.deserialize(&bytes),
+            Err(StateError::Migration(1, _))
+        ));
+
+        ctx.set_raw_state(&ctx.get_dialogue(), bytes);
+        let message = ctx.make_message("3", false, ChatType::Private);
+        ctx.dispatch(make_message_update(message)).await.unwrap();
+        ctx.check_the_state_and_text(State::Start, text::SORRY_BOT_UPDATED)
+            .await;
+    }
+
+    #[test]
+    fn test_a_state_from_the_future() {
+        // A newer bot saved it, and then it was rolled back, the old bot can't know what it means
+        let future = json!({ "version": STATE_VERSION + 1, "state": "Start" });
+        let result = VersionedCbor.deserialize(&serde_cbor::to_vec(&future).unwrap());
+        assert!(matches!(result, Err(StateError::FromTheFuture(_))));
+    }
+}
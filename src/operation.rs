@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, UserId};
+
+/*
+    What the calculator can do with the two numbers
+
+    The operation comes from the button that the user pressed, as its callback data. Telegram
+    doesn't check that data, anyone can send any string as it, so it's parsed with `from_data`,
+    and the unknown ones are rejected, instead of being saved into the state.
+    The data is the same as the operation is saved with in the state, e.g. "add"
+
+    In groups everyone sees everyone's keyboards, so the buttons also know whose keyboard it is:
+    "add:123456789", the operation and the id of the user who sent /start. The keyboards from
+    before that have only the operation, and anyone can press them
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 6] = [
+        // In the order of the buttons
+        Operation::Add,
+        Operation::Subtract,
+        Operation::Multiply,
+        Operation::Divide,
+        Operation::Modulo,
+        Operation::Power,
+    ];
+
+    pub fn label(self) -> &'static str {
+        // The text on the button
+        match self {
+            Operation::Add => "Add",
+            Operation::Subtract => "Subtract",
+            Operation::Multiply => "Multiply",
+            Operation::Divide => "Divide",
+            Operation::Modulo => "Modulo",
+            Operation::Power => "Power",
+        }
+    }
+
+    pub fn to_data(self) -> &'static str {
+        // The callback data of the button
+        match self {
+            Operation::Add => "add",
+            Operation::Subtract => "subtract",
+            Operation::Multiply => "multiply",
+            Operation::Divide => "divide",
+            Operation::Modulo => "modulo",
+            Operation::Power => "power",
+        }
+    }
+
+    pub fn from_data(data: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|operation| operation.to_data() == data)
+    }
+
+    pub fn to_button_data(self, owner: Option<UserId>) -> String {
+        match owner {
+            Some(owner) => format!("{}:{}", self.to_data(), owner),
+            None => self.to_data().to_string(),
+        }
+    }
+
+    pub fn from_button_data(data: &str) -> Option<(Self, Option<UserId>)> {
+        // The operation, and the user whose keyboard it is, if the button knows it
+        match data.split_once(':') {
+            Some((operation, owner)) => Some((
+                Self::from_data(operation)?,
+                Some(UserId(owner.parse().ok()?)),
+            )),
+            None => Some((Self::from_data(data)?, None)),
+        }
+    }
+
+    pub fn keyboard(owner: Option<UserId>) -> InlineKeyboardMarkup {
+        // Two buttons in a row, so the labels fit on a phone
+        let rows = Self::ALL.chunks(2).map(|row| {
+            row.iter()
+                .map(|operation| {
+                    InlineKeyboardButton::callback(
+                        operation.label(),
+                        operation.to_button_data(owner),
+                    )
+                })
+                .collect::<Vec<_>>()
+        });
+        InlineKeyboardMarkup::new(rows)
+    }
+
+    pub fn apply(self, first: i32, second: i32) -> Option<i32> {
+        // None if there is no answer: division by zero, a negative power, or the result doesn't
+        // fit into i32
+        match self {
+            Operation::Add => first.checked_add(second),
+            Operation::Subtract => first.checked_sub(second),
+            Operation::Multiply => first.checked_mul(second),
+            Operation::Divide => first.checked_div(second),
+            Operation::Modulo => first.checked_rem(second),
+            Operation::Power => first.checked_pow(u32::try_from(second).ok()?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_the_callback_data() {
+        // Every operation comes back from its data, and the data is the same as in the state
+        for operation in Operation::ALL {
+            assert_eq!(Operation::from_data(operation.to_data()), Some(operation));
+            let json = serde_json::to_value(operation).unwrap();
+            assert_eq!(json, operation.to_data());
+        }
+        for data in ["", "Add", "add ", "sqrt", "{\"operation\":\"add\"}"] {
+            assert_eq!(Operation::from_data(data), None);
+        }
+
+        // The buttons also know whose keyboard they are on
+        let data = Operation::Power.to_button_data(Some(UserId(42)));
+        assert_eq!(data, "power:42");
+        let button = Operation::from_button_data(&data);
+        assert_eq!(button, Some((Operation::Power, Some(UserId(42)))));
+        assert_eq!(
+            Operation::from_button_data("power"),
+            Some((Operation::Power, None))
+        );
+        for data in ["power:", "power:me", "sqrt:42"] {
+            assert_eq!(Operation::from_button_data(data), None);
+        }
+    }
+
+    #[test]
+    fn test_the_results() {
+        assert_eq!(Operation::Add.apply(2, 3), Some(5));
+        assert_eq!(Operation::Subtract.apply(2, 3), Some(-1));
+        assert_eq!(Operation::Multiply.apply(2, 3), Some(6));
+        assert_eq!(Operation::Divide.apply(7, 2), Some(3));
+        assert_eq!(Operation::Modulo.apply(7, 2), Some(1));
+        assert_eq!(Operation::Power.apply(2, 3), Some(8));
+        // And the ones without an answer
+        assert_eq!(Operation::Divide.apply(1, 0), None);
+        assert_eq!(Operation::Modulo.apply(1, 0), None);
+        assert_eq!(Operation::Power.apply(2, -1), None);
+        assert_eq!(Operation::Multiply.apply(i32::MAX, 2), None);
+    }
+}
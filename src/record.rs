@@ -0,0 +1,179 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use teloxide::{dispatching::dialogue::GetChatId, types::Recipient, types::Update};
+
+use crate::intercept::SentRequest;
+
+/*
+    Recording the real updates, to replay them in tests
+
+    If a user hits a bug, you don't want to guess what exactly they sent. Set BOT_RECORD_DIR in
+    .env (for example BOT_RECORD_DIR=recordings), and the bot will write every update it gets,
+    and every request it sends, into a file per chat: recordings/<chat id>.jsonl
+    Then copy that file into your tests, and replay it with `replay_recording` from test_utils.rs,
+    it will show if the bot does something different now.
+
+    Be careful, the recordings have everything users sent, don't commit them if that's private!
+*/
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recorded {
+    // One line of the recording file
+    Update(Box<Update>), // Boxed, because an update is much bigger than a request
+    Request { method: String, json: Value },
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+    lock: Mutex<()>, // So that two lines written at the same time don't get mixed up
+}
+
+impl Recorder {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).expect("Can't create the directory for the recordings");
+        Self {
+            dir,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        // Recording is off, if BOT_RECORD_DIR isn't set, or is empty
+        let dir = dotenvy::var("BOT_RECORD_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())?;
+        log::info!("Recording the updates and requests into {}", dir);
+        Some(Self::new(dir))
+    }
+
+    pub fn record_update(&self, update: &Update) {
+        // Updates without a chat (like inline queries) are not recorded, there is no file for them
+        if let Some(chat_id) = update.chat_id() {
+            self.write(
+                &chat_id.to_string(),
+                &Recorded::Update(Box::new(update.clone())),
+            );
+        }
+    }
+
+    pub fn record_request(&self, request: &SentRequest) {
+        // The same with the requests without a chat, like answer_callback_query
+        let file_name = match &request.chat_id {
+            Some(Recipient::Id(chat_id)) => chat_id.to_string(),
+            Some(Recipient::ChannelUsername(username)) => username.trim_start_matches('@').into(),
+            None => return,
+        };
+        let recorded = Recorded::Request {
+            method: request.method.to_string(),
+            json: request.json.clone(),
+        };
+        self.write(&file_name, &recorded);
+    }
+
+    fn write(&self, file_name: &str, recorded: &Recorded) {
+        let _lock = self.lock.lock().unwrap();
+        let path = self.dir.join(format!("{file_name}.jsonl"));
+        let line = serde_json::to_string(recorded).unwrap();
+        // The bot shouldn't crash because of the recording, so the errors are just logged
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| writeln!(file, "{line}"));
+        if let Err(error) = result {
+            log::error!("Can't record into {}: {}", path.display(), error);
+        }
+    }
+}
+
+#[cfg(test)] // The real bot only writes the recordings, the tests read them
+pub fn read_recording(path: &std::path::Path) -> Vec<Recorded> {
+    let text = fs::read_to_string(path)
+        .unwrap_or_else(|error| panic!("Can't read {}: {}", path.display(), error));
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .unwrap_or_else(|error| panic!("{}:{}: {}", path.display(), index + 1, error))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_utils::*;
+    use teloxide::payloads::SendMessage;
+    use teloxide::types::ChatId;
+
+    fn make_recordings_dir(ctx: &TestContext) -> PathBuf {
+        std::env::temp_dir().join(format!("recordings_{}", ctx.user_id))
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        // Like in production: the bot records the updates and the requests while it works
+        let mut ctx = TestContext::new();
+        let dir = make_recordings_dir(&ctx);
+        let recorder = Arc::new(Recorder::new(&dir));
+        ctx.bot = ctx.bot.clone().with_recorder(recorder.clone());
+        let updates = [
+            make_message_update(ctx.make_message("/start", true, ChatType::Private)),
+            make_callback_query_update(ctx.make_callback_query("add", ChatType::Private)),
+            make_message_update(ctx.make_message("2", false, ChatType::Private)),
+        ];
+        for update in updates {
+            recorder.record_update(&update); // main.rs does it with .inspect() before the schema
+            ctx.dispatch(update).await.unwrap();
+        }
+
+        // The bot didn't change, so it does exactly the same thing again
+        let path = dir.join(format!("{}.jsonl", ctx.user_id));
+        assert_eq!(read_recording(&path).len(), 3 + 4); // 3 updates and 4 requests
+        let replayed = replay_recording(&path).await;
+        assert_eq!(replayed.get_sent_requests().len(), 4);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "diverged at update 1")]
+    async fn test_replay_shows_the_difference() {
+        // A recording from an older version of the bot, that answered differently
+        let ctx = TestContext::new();
+        let request = SentRequest::new(SendMessage::new(
+            ChatId(ctx.user_id),
+            "Hi! What do you want?",
+        ));
+        let recording = [
+            Recorded::Update(Box::new(make_message_update(ctx.make_message(
+                "/start",
+                true,
+                ChatType::Private,
+            )))),
+            Recorded::Request {
+                method: request.method.to_string(),
+                json: request.json,
+            },
+        ];
+        let lines: Vec<String> = recording
+            .iter()
+            .map(|recorded| serde_json::to_string(recorded).unwrap())
+            .collect();
+        let path = std::env::temp_dir().join(format!("old_recording_{}.jsonl", ctx.user_id));
+        fs::write(&path, lines.join("\n")).unwrap();
+        ctx.replay(&path).await;
+    }
+}
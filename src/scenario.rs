@@ -0,0 +1,477 @@
+#![allow(dead_code)] // Only the tests use it
+use std::fmt::{self, Debug, Display};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::test_utils::{make_callback_query_update, make_message_update, ChatType, TestContext};
+use crate::State;
+
+/*
+    Scenarios: the whole dialogue in one test
+
+    Instead of running every update by hand, and checking the state between them, you just write
+    what the user does and what the bot should answer, step by step:
+
+    Scenario::new()
+        .send("/start")
+        .expect_texts(&[text::WHAT_DO_YOU_WANT])
+        .expect_keyboard(&[&["Add", "Subtract"], &["Multiply", "Divide"], &["Modulo", "Power"]])
+        .press_button("Add")
+        .expect_state(State::GetFirstNumber { operation: Operation::Add })
+        .run()
+        .await;
+
+    Every action starts a new step, and the expectations after it are checked after that step.
+    If something is different, the test fails on the first step that went wrong, and shows what
+    was expected and what the bot did instead.
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserAction {
+    Send(String),        // A text message, commands are detected by the "/" at the start
+    PressButton(String), // Presses the inline button with this text, on the last keyboard
+    SendPhoto,
+}
+
+impl Display for UserAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserAction::Send(text) => write!(f, "send {:?}", text),
+            UserAction::PressButton(label) => write!(f, "press the button {:?}", label),
+            UserAction::SendPhoto => write!(f, "send a photo"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)] // So a typo in a scenario file isn't silently ignored
+pub struct Expected {
+    // Everything is optional, check only what you care about
+    pub texts: Option<Vec<String>>, // Every text the bot sent in this step, in order
+    pub keyboard: Option<Vec<Vec<String>>>, // The buttons of the last keyboard in this step
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    // This lets the scenario files write the states like `GetFirstNumber: { operation: add }`
+    pub state: Option<State>, // The state after this step
+}
+
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub action: UserAction,
+    pub expected: Expected,
+    pub location: Option<String>, // The file and the line, if the step is from a scenario file
+}
+
+impl Step {
+    fn describe(&self, number: usize) -> String {
+        match &self.location {
+            Some(location) => format!("step {number} ({}) at {location}", self.action),
+            None => format!("step {number} ({})", self.action),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    chat_type: ChatType, // Where the user talks to the bot
+    start_state: State,
+    steps: Vec<Step>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        // From the very beginning, in a private chat
+        Self {
+            chat_type: ChatType::Private,
+            start_state: State::default(),
+            steps: vec![],
+        }
+    }
+
+    pub fn in_chat(mut self, chat_type: ChatType) -> Self {
+        self.chat_type = chat_type;
+        self
+    }
+
+    pub fn starting_from(mut self, state: State) -> Self {
+        // If you don't want to go through the whole dialogue every time
+        self.start_state = state;
+        self
+    }
+
+    /*
+        What the user does
+    */
+
+    pub fn action(mut self, action: UserAction) -> Self {
+        self.steps.push(Step {
+            action,
+            expected: Expected::default(),
+            location: None,
+        });
+        self
+    }
+
+    pub fn send(self, text: &str) -> Self {
+        self.action(UserAction::Send(text.to_string()))
+    }
+
+    pub fn press_button(self, label: &str) -> Self {
+        self.action(UserAction::PressButton(label.to_string()))
+    }
+
+    pub fn send_photo(self) -> Self {
+        self.action(UserAction::SendPhoto)
+    }
+
+    /*
+        What the bot should do after the last action
+    */
+
+    fn last_expected(&mut self) -> &mut Expected {
+        &mut self
+            .steps
+            .last_mut()
+            .expect("Add an action before the expectations")
+            .expected
+    }
+
+    pub fn expect_texts(mut self, texts: &[&str]) -> Self {
+        self.last_expected().texts = Some(texts.iter().map(|text| text.to_string()).collect());
+        self
+    }
+
+    pub fn expect_keyboard(mut self, keyboard: &[&[&str]]) -> Self {
+        self.last_expected().keyboard = Some(
+            keyboard
+                .iter()
+                .map(|row| row.iter().map(|label| label.to_string()).collect())
+                .collect(),
+        );
+        self
+    }
+
+    pub fn expect_state(mut self, state: State) -> Self {
+        self.last_expected().state = Some(state);
+        self
+    }
+
+    /*
+        Running it
+    */
+
+    pub async fn run(self) -> TestContext {
+        // Returns the context, so you can check anything else after the scenario
+        let ctx = TestContext::new();
+        self.run_in(&ctx).await;
+        ctx
+    }
+
+    pub async fn run_in(self, ctx: &TestContext) {
+        ctx.get_dialogue_in(self.chat_type.clone())
+            .update(self.start_state.clone())
+            .await
+            .unwrap();
+
+        for (number, step) in self.steps.iter().enumerate() {
+            let number = number + 1; // People count from 1
+            println!("Scenario step {number}: {}", step.action);
+            // If a handler panics, this is the last thing printed, so you know which step it was
+
+            let sent_before = ctx.get_sent_requests().len();
+            let update = match &step.action {
+                UserAction::Send(text) => make_message_update(ctx.make_message(
+                    text,
+                    text.starts_with('/'),
+                    self.chat_type.clone(),
+                )),
+                UserAction::SendPhoto => {
+                    make_message_update(ctx.make_photo(false, self.chat_type.clone()))
+                }
+                UserAction::PressButton(label) => {
+                    let message = ctx.find_message_with_button(label).unwrap_or_else(|| {
+                        panic!(
+                            "Scenario diverged at {}:\nthere is no button {:?} in the keyboards the bot sent",
+                            step.describe(number),
+                            label
+                        )
+                    });
+                    make_callback_query_update(ctx.press_button(&message, label))
+                }
+            };
+            let state = ctx.get_state_in(self.chat_type.clone()).await;
+            ctx.run_update(update, state).await.unwrap();
+
+            let sent = &ctx.get_sent_requests()[sent_before..];
+            let expected = &step.expected;
+            if let Some(texts) = &expected.texts {
+                let sent_texts: Vec<String> = sent
+                    .iter()
+                    .filter_map(|request| request.text().map(|text| text.to_string()))
+                    .collect();
+                check(number, step, "texts", texts, &sent_texts);
+            }
+            if let Some(keyboard) = &expected.keyboard {
+                let sent_keyboard: Vec<Vec<String>> = sent
+                    .iter()
+                    .rev()
+                    .find_map(|request| request.inline_keyboard())
+                    .map(|keyboard| {
+                        keyboard
+                            .inline_keyboard
+                            .iter()
+                            .map(|row| row.iter().map(|button| button.text.clone()).collect())
+                            .collect()
+                    })
+                    .unwrap_or_default(); // No keyboard is the same as an empty one
+                check(number, step, "keyboard", keyboard, &sent_keyboard);
+            }
+            if let Some(state) = &expected.state {
+                let got = ctx.get_state_in(self.chat_type.clone()).await;
+                check(number, step, "state", state, &got);
+            }
+        }
+    }
+}
+
+fn check<T: Debug + PartialEq>(number: usize, step: &Step, what: &str, expected: &T, got: &T) {
+    if expected != got {
+        panic!(
+            "Scenario diverged at {}, the {what} are different:\n{}",
+            step.describe(number),
+            diff(&format!("{:#?}", expected), &format!("{:#?}", got))
+        );
+    }
+}
+
+/*
+    Scenario files
+
+    The same scenarios, but in yaml (or json) files in tests/scenarios, so you can write them
+    without touching the rust code. See tests/scenarios/add_two_numbers.yaml for an example,
+    test_scenario_files() below runs all of them.
+*/
+
+pub const SCENARIOS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scenarios");
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    #[serde(default)]
+    chat: Option<ChatType>,
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    start_state: Option<State>,
+    steps: Vec<StepFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepFile {
+    // Exactly one of the actions
+    send: Option<String>,
+    press_button: Option<String>,
+    #[serde(default)]
+    send_photo: bool,
+    #[serde(default)]
+    expect: Expected,
+}
+
+impl Scenario {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        // The errors already have the file and the line in them, so they can be shown as is
+        let name = path
+            .strip_prefix(env!("CARGO_MANIFEST_DIR"))
+            .unwrap_or(path)
+            .display()
+            .to_string();
+        let text = std::fs::read_to_string(path).map_err(|error| format!("{name}: {error}"))?;
+        let file: ScenarioFile =
+            serde_yaml::from_str(&text).map_err(|error| match error.location() {
+                Some(location) => {
+                    format!("{name}:{}:{}: {error}", location.line(), location.column())
+                }
+                None => format!("{name}: {error}"),
+            })?;
+
+        let step_lines = find_step_lines(&text);
+        let mut scenario = Scenario::new()
+            .in_chat(file.chat.unwrap_or(ChatType::Private))
+            .starting_from(file.start_state.unwrap_or_default());
+        for (index, step) in file.steps.into_iter().enumerate() {
+            // If the lines weren't found (like in json), at least the file will be there
+            let location = match step_lines.get(index) {
+                Some(line) => format!("{name}:{line}"),
+                None => name.clone(),
+            };
+            let action = match (step.send, step.press_button, step.send_photo) {
+                (Some(text), None, false) => UserAction::Send(text),
+                (None, Some(label), false) => UserAction::PressButton(label),
+                (None, None, true) => UserAction::SendPhoto,
+                _ => {
+                    return Err(format!(
+                        "{location}: a step needs exactly one of `send`, `press_button` or `send_photo`"
+                    ))
+                }
+            };
+            scenario.steps.push(Step {
+                action,
+                expected: step.expect,
+                location: Some(location),
+            });
+        }
+        Ok(scenario)
+    }
+}
+
+pub fn find_scenario_files(dir: &str) -> Vec<std::path::PathBuf> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| {
+                    matches!(
+                        path.extension().and_then(|extension| extension.to_str()),
+                        Some("yaml" | "yml" | "json")
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default(); // No directory - no scenarios
+    files.sort(); // So they always run in the same order
+    files
+}
+
+fn find_step_lines(text: &str) -> Vec<usize> {
+    // serde_yaml doesn't tell where the values were in the file, so we look for the steps
+    // ourselves: they are the "- " lines under "steps:", with the same indentation as the first one
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .skip_while(|(_, line)| !line.trim_start().starts_with("steps:"));
+    let Some((_, steps_line)) = lines.next() else {
+        return vec![];
+    };
+    let steps_indent = steps_line.len() - steps_line.trim_start().len();
+
+    let mut step_lines = vec![];
+    let mut step_indent = None;
+    for (index, line) in lines {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        let is_item = trimmed.starts_with('-');
+        match step_indent {
+            None if is_item && indent >= steps_indent => step_indent = Some(indent),
+            None => break, // The steps aren't a yaml list, like in json
+            Some(step_indent) if indent < step_indent => break,
+            Some(step_indent) if indent == step_indent && !is_item => break,
+            _ => {}
+        }
+        if is_item && Some(indent) == step_indent {
+            step_lines.push(index + 1); // People count lines from 1 too
+        }
+    }
+    step_lines
+}
+
+pub fn diff(expected: &str, got: &str) -> String {
+    // A simple line by line diff, like in git: "-" is what was expected, "+" is what we got.
+    // It finds the longest common subsequence of the lines, everything else is the difference
+    let expected: Vec<&str> = expected.lines().collect();
+    let got: Vec<&str> = got.lines().collect();
+    let mut common = vec![vec![0; got.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..got.len()).rev() {
+            common[i][j] = if expected[i] == got[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < got.len() {
+        if i < expected.len() && j < got.len() && expected[i] == got[j] {
+            result.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == got.len() || (i < expected.len() && common[i + 1][j] >= common[i][j + 1]) {
+            result.push(format!("- {}", expected[i]));
+            i += 1;
+        } else {
+            result.push(format!("+ {}", got[j]));
+            j += 1;
+        }
+    }
+    result.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let diff = diff(
+            "[\n    \"a\",\n    \"b\",\n]",
+            "[\n    \"a\",\n    \"c\",\n]",
+        );
+        assert_eq!(diff, "  [\n      \"a\",\n-     \"b\",\n+     \"c\",\n  ]");
+    }
+
+    #[tokio::test]
+    async fn test_scenario_files() {
+        // Runs every scenario in tests/scenarios, and shows all the ones that failed at once
+        let mut failures = vec![];
+        for path in find_scenario_files(SCENARIOS_DIR) {
+            let scenario = match Scenario::from_file(&path) {
+                Ok(scenario) => scenario,
+                Err(error) => {
+                    failures.push(error);
+                    continue;
+                }
+            };
+            // A separate task, so that one failed scenario doesn't stop the others
+            if let Err(error) = tokio::spawn(scenario.run()).await {
+                let panic = error.into_panic();
+                let message = match panic.downcast_ref::<String>() {
+                    Some(message) => message.clone(),
+                    None => format!("{}: {:?}", path.display(), panic),
+                };
+                failures.push(message);
+            }
+        }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+    }
+
+    #[test]
+    fn test_scenario_file_errors_have_lines() {
+        let dir = std::env::temp_dir().join(format!("scenarios_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("broken.yaml");
+        std::fs::write(
+            &path,
+            "steps:\n  - send: /start\n  - press_button: Add\n    send: Add\n",
+        )
+        .unwrap();
+
+        let error = Scenario::from_file(&path).unwrap_err();
+        assert!(
+            error.ends_with(
+                "broken.yaml:3: a step needs exactly one of `send`, `press_button` or `send_photo`"
+            ),
+            "{error}"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
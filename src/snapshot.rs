@@ -0,0 +1,204 @@
+#![allow(dead_code)]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+use teloxide::types::Recipient;
+
+use crate::intercept::SentRequest;
+use crate::scenario::diff;
+use crate::State;
+
+/*
+    Snapshots of what the bot sent
+
+    Instead of writing every text into the test, the test saves everything the bot sent (and the
+    state it ended up in) into a readable file in src/snapshots, and the next time it just checks
+    that nothing changed. If you changed a text on purpose, look at the diff, and if it's right, run
+    `BOT_UPDATE_SNAPSHOTS=true cargo test` to overwrite the old snapshots. Don't forget to commit them!
+    A new test has no snapshot yet, so it fails too, and saves what it got next to where the
+    snapshot should be, as <name>.snap.new. Look at it, and accept it the same way. That way a
+    deleted or forgotten snapshot fails on CI, instead of being quietly made again
+
+    The file looks like this:
+
+    SendMessage to the user
+      parse mode: HTML
+      text: What do you want to do with two numbers?
+      keyboard:
+        [Add] [Subtract]
+        [Multiply] [Divide]
+        [Modulo] [Power]
+
+    State: WhatDoYouWant
+*/
+
+pub const SNAPSHOTS_DIR: &str = "src/snapshots";
+
+pub struct Chats {
+    // The ids change from test to test, so in the snapshot they are replaced with names
+    pub user_id: i64,
+    pub group_id: i64,
+}
+
+pub fn make_transcript(requests: &[SentRequest], state: &State, chats: &Chats) -> String {
+    // An empty line between the requests, so it's easy to see where one ends
+    let mut blocks: Vec<String> = requests
+        .iter()
+        .map(|request| describe_request(request, chats))
+        .collect();
+    blocks.push(format!("State: {:#?}", state));
+    blocks.join("\n\n") + "\n"
+}
+
+fn describe_request(request: &SentRequest, chats: &Chats) -> String {
+    let mut lines = vec![match &request.chat_id {
+        Some(chat_id) => format!("{} to {}", request.method, describe_chat(chat_id, chats)),
+        None => request.method.to_string(),
+    }];
+    // Only the things that the user sees, the rest changes too often to be in a snapshot
+    let json = &request.json;
+    if let Some(parse_mode) = json["parse_mode"].as_str() {
+        lines.push(format!("  parse mode: {}", parse_mode));
+    }
+    if let Some(text) = json["text"].as_str().or(json["caption"].as_str()) {
+        // The other lines of the text are indented too, so it's clear where the text ends
+        lines.push(format!("  text: {}", text.replace('\n', "\n        ")));
+    }
+    if let Some(keyboard) = describe_keyboard(&json["reply_markup"]) {
+        lines.push(format!("  keyboard:{}", keyboard));
+    } else if request.method == "EditMessageReplyMarkup" {
+        // An edit without a keyboard removes it
+        lines.push("  keyboard: none".to_string());
+    }
+    lines.join("\n")
+}
+
+fn describe_chat(chat_id: &Recipient, chats: &Chats) -> String {
+    match chat_id {
+        Recipient::Id(id) if id.0 == chats.user_id => "the user".to_string(),
+        Recipient::Id(id) if id.0 == chats.group_id => "the group".to_string(),
+        Recipient::Id(id) => id.to_string(),
+        Recipient::ChannelUsername(username) => username.clone(),
+    }
+}
+
+fn describe_keyboard(reply_markup: &Value) -> Option<String> {
+    // Inline and normal keyboards both look like rows of [buttons]
+    let rows = reply_markup["inline_keyboard"]
+        .as_array()
+        .or(reply_markup["keyboard"].as_array());
+    if let Some(rows) = rows {
+        if rows.is_empty() {
+            return Some(" none".to_string());
+        }
+        let rows: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                let buttons: Vec<String> = row
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|button| format!("[{}]", button["text"].as_str().unwrap_or("?")))
+                    .collect();
+                format!("\n    {}", buttons.join(" "))
+            })
+            .collect();
+        return Some(rows.concat());
+    }
+    if reply_markup["remove_keyboard"].as_bool() == Some(true) {
+        return Some(" removed".to_string());
+    }
+    None
+}
+
+pub fn snapshot_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(SNAPSHOTS_DIR)
+        .join(format!("{name}.snap"))
+}
+
+pub fn should_update_snapshots() -> bool {
+    // Not from .env on purpose, you don't want to accept every change forever by accident
+    std::env::var("BOT_UPDATE_SNAPSHOTS").is_ok_and(|value| value == "true" || value == "1")
+}
+
+pub fn check_snapshot(path: &Path, transcript: &str, update: bool) {
+    let old_transcript = fs::read_to_string(path).ok();
+    if old_transcript.as_deref() == Some(transcript) {
+        return;
+    }
+    if update {
+        write_snapshot(path, transcript);
+        return;
+    }
+    let Some(old_transcript) = old_transcript else {
+        // Nothing to compare it with, so it's only saved for you to look at
+        let new_path = path.with_extension("snap.new");
+        write_snapshot(&new_path, transcript);
+        panic!(
+            "There is no snapshot {}, the new one is in {}. If it's right, run `BOT_UPDATE_SNAPSHOTS=true cargo test`:\n{}",
+            path.display(),
+            new_path.display(),
+            transcript
+        );
+    };
+    panic!(
+        "The snapshot {} is different, if the new one is right, run `BOT_UPDATE_SNAPSHOTS=true cargo test`:\n{}",
+        path.display(),
+        diff(&old_transcript, transcript)
+    );
+}
+
+fn write_snapshot(path: &Path, transcript: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, transcript)
+        .unwrap_or_else(|error| panic!("Can't write {}: {}", path.display(), error));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[tokio::test]
+    #[should_panic(expected = "is different")]
+    async fn test_snapshot_shows_the_difference() {
+        let ctx = TestContext::new();
+        let message = ctx.make_message("/start", true, ChatType::Private);
+        ctx.run_update(make_message_update(message), State::Start)
+            .await
+            .unwrap();
+
+        // A snapshot from an older version of the bot, with a different text
+        let path = std::env::temp_dir().join(format!("old_snapshot_{}.snap", ctx.user_id));
+        fs::write(
+            &path,
+            "SendMessage to the user\n  text: Hi! What do you want?\n\nState: WhatDoYouWant\n",
+        )
+        .unwrap();
+        let transcript = make_transcript(
+            &ctx.get_sent_requests(),
+            &ctx.get_state().await,
+            &Chats {
+                user_id: ctx.user_id,
+                group_id: ctx.group_id,
+            },
+        );
+        check_snapshot(&path, &transcript, false);
+    }
+
+    #[test]
+    fn test_a_missing_snapshot_fails() {
+        let path = std::env::temp_dir().join(format!("missing_snapshot_{}.snap", TEST_USER_ID));
+        let result = std::panic::catch_unwind(|| check_snapshot(&path, "State: Start\n", false));
+        assert!(result.is_err());
+        // It isn't made, so the next run fails too, but the transcript is there to look at
+        assert!(!path.exists());
+        let new_path = path.with_extension("snap.new");
+        assert_eq!(fs::read_to_string(&new_path).unwrap(), "State: Start\n");
+        fs::remove_file(new_path).unwrap();
+    }
+}
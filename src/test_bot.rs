@@ -0,0 +1,324 @@
+use std::{
+    future::{Future, IntoFuture},
+    pin::Pin,
+    sync::Arc,
+};
+
+use teloxide::{
+    prelude::*,
+    requests::{HasPayload, Output},
+    types::*,
+    RequestError,
+};
+use url::Url;
+
+#[cfg(test)]
+use crate::failures::Failures;
+use crate::history::History;
+use crate::identity::TestBotIdentity;
+use crate::intercept::{intercept, InterceptMode, InterceptPayload, Journal, SentRequest};
+use crate::record::Recorder;
+
+/*
+    A bot that routes every request through the interception layer in intercept.rs
+
+    It implements `Requester` exactly like the adaptors from teloxide (`Trace`, `DefaultParseMode`,
+    etc) do, so handlers can take any `B: Requester` instead of `Bot`, and in tests we just give
+    them a `TestBot`. This way you can't "forget" to intercept something, every single request
+    goes through the `InterceptedRequest` below.
+
+    What happens with the requests depends on the `InterceptMode`, so the real bot uses it too:
+    normally it just sends everything, but with BOT_DRY_RUN=true it only logs what it would've sent
+*/
+
+#[derive(Clone, Debug)]
+pub struct TestBot {
+    bot: Bot, // The real bot, that sends the requests that aren't intercepted
+    mode: InterceptMode,
+    journal: Journal, // The requests this bot intercepted, the clones of the bot share it
+    history: History, // The messages in the fake chats, see history.rs
+    #[cfg(test)]
+    failures: Failures, // The requests that have to fail, see failures.rs
+    recorder: Option<Arc<Recorder>>, // Writes down every request, see record.rs
+    identity: TestBotIdentity, // Who the bot is in the fake responses, see identity.rs
+}
+
+impl TestBot {
+    pub fn new(bot: Bot) -> Self {
+        // Intercepts everything, for tests
+        Self::with_mode(bot, InterceptMode::Intercept)
+    }
+
+    pub fn with_mode(bot: Bot, mode: InterceptMode) -> Self {
+        Self {
+            bot,
+            mode,
+            journal: Journal::default(),
+            history: History::default(),
+            #[cfg(test)]
+            failures: Failures::default(),
+            recorder: None,
+            identity: TestBotIdentity::default(),
+        }
+    }
+
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn with_identity(mut self, identity: TestBotIdentity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    pub fn inner(&self) -> &Bot {
+        &self.bot
+    }
+
+    pub fn mode(&self) -> InterceptMode {
+        self.mode
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    #[cfg(test)]
+    pub fn failures(&self) -> &Failures {
+        &self.failures
+    }
+
+    pub fn recorder(&self) -> Option<Arc<Recorder>> {
+        self.recorder.clone()
+    }
+
+    pub fn identity(&self) -> &TestBotIdentity {
+        &self.identity
+    }
+}
+
+// The same future type for both the fake and the real response, so that `Request::Send` has
+// a name. Boxing isn't the fastest, but we don't care about speed in tests
+pub type InterceptedSend<T> = Pin<Box<dyn Future<Output = Result<T, RequestError>> + Send>>;
+
+#[derive(Clone, Debug)]
+pub struct InterceptedRequest<R> {
+    inner: R,     // The request that the real bot would've sent
+    bot: TestBot, // The bot that made this request, with its journal, history, etc
+}
+
+impl<R> InterceptedRequest<R> {
+    pub fn new(inner: R, bot: TestBot) -> Self {
+        Self { inner, bot }
+    }
+}
+
+impl<R> InterceptedRequest<R>
+where
+    R: HasPayload,
+    R::Payload: InterceptPayload,
+{
+    fn record(&self) {
+        // Both the intercepted and the real requests are recorded
+        if let Some(recorder) = &self.bot.recorder {
+            recorder.record_request(&SentRequest::new(self.inner.payload_ref().clone()));
+        }
+    }
+}
+
+impl<R> HasPayload for InterceptedRequest<R>
+where
+    R: HasPayload,
+{
+    // Because of this, all the setters (like `.reply_markup()`) work like on a normal request
+    type Payload = R::Payload;
+
+    fn payload_mut(&mut self) -> &mut Self::Payload {
+        self.inner.payload_mut()
+    }
+
+    fn payload_ref(&self) -> &Self::Payload {
+        self.inner.payload_ref()
+    }
+}
+
+impl<R> Request for InterceptedRequest<R>
+where
+    R: Request<Err = RequestError> + 'static,
+    R::Payload: InterceptPayload,
+    Output<R>: Send + 'static,
+{
+    type Err = RequestError;
+    type Send = InterceptedSend<Output<R>>;
+    type SendRef = InterceptedSend<Output<R>>;
+
+    fn send(self) -> Self::Send {
+        self.record();
+        if self.bot.mode.should_intercept::<R::Payload>() {
+            // Telegram never sees this request, we make up the response ourselves
+            let response = intercept(self.inner.payload_ref(), &self.bot);
+            return Box::pin(std::future::ready(response));
+        }
+        Box::pin(self.inner.send())
+    }
+
+    fn send_ref(&self) -> Self::SendRef {
+        self.record();
+        if self.bot.mode.should_intercept::<R::Payload>() {
+            let response = intercept(self.inner.payload_ref(), &self.bot);
+            return Box::pin(std::future::ready(response));
+        }
+        Box::pin(self.inner.send_ref())
+    }
+}
+
+impl<R> IntoFuture for InterceptedRequest<R>
+where
+    R: Request<Err = RequestError> + 'static,
+    R::Payload: InterceptPayload,
+    Output<R>: Send + 'static,
+{
+    // This is what makes `.await` work on the request
+    type Output = Result<Output<R>, RequestError>;
+    type IntoFuture = InterceptedSend<Output<R>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.send()
+    }
+}
+
+// These two macros are passed into `requester_forward!` (see requester_forward.rs),
+// it generates every method of `Requester` with them
+macro_rules! fty {
+    ($T:ident) => {
+        InterceptedRequest<<Bot as Requester>::$T>
+    };
+}
+
+macro_rules! fwd_intercept {
+    ($m:ident $this:ident ($($arg:ident : $T:ty),*)) => {
+        InterceptedRequest::new($this.inner().$m($($arg),*), $this.clone())
+    };
+}
+
+impl Requester for TestBot {
+    type Err = RequestError;
+
+    requester_forward! {
+        get_me,
+        log_out,
+        close,
+        get_updates,
+        set_webhook,
+        delete_webhook,
+        get_webhook_info,
+        forward_message,
+        copy_message,
+        send_message,
+        send_photo,
+        send_audio,
+        send_document,
+        send_video,
+        send_animation,
+        send_voice,
+        send_video_note,
+        send_media_group,
+        send_location,
+        edit_message_live_location,
+        edit_message_live_location_inline,
+        stop_message_live_location,
+        stop_message_live_location_inline,
+        send_venue,
+        send_contact,
+        send_poll,
+        send_dice,
+        send_chat_action,
+        get_user_profile_photos,
+        get_file,
+        kick_chat_member,
+        ban_chat_member,
+        unban_chat_member,
+        restrict_chat_member,
+        promote_chat_member,
+        set_chat_administrator_custom_title,
+        ban_chat_sender_chat,
+        unban_chat_sender_chat,
+        set_chat_permissions,
+        export_chat_invite_link,
+        create_chat_invite_link,
+        edit_chat_invite_link,
+        revoke_chat_invite_link,
+        approve_chat_join_request,
+        decline_chat_join_request,
+        set_chat_photo,
+        delete_chat_photo,
+        set_chat_title,
+        set_chat_description,
+        pin_chat_message,
+        unpin_chat_message,
+        unpin_all_chat_messages,
+        leave_chat,
+        get_chat,
+        get_chat_administrators,
+        get_chat_members_count,
+        get_chat_member_count,
+        get_chat_member,
+        set_chat_sticker_set,
+        delete_chat_sticker_set,
+        get_forum_topic_icon_stickers,
+        create_forum_topic,
+        edit_forum_topic,
+        close_forum_topic,
+        reopen_forum_topic,
+        delete_forum_topic,
+        unpin_all_forum_topic_messages,
+        edit_general_forum_topic,
+        close_general_forum_topic,
+        reopen_general_forum_topic,
+        hide_general_forum_topic,
+        unhide_general_forum_topic,
+        answer_callback_query,
+        set_my_commands,
+        get_my_commands,
+        set_chat_menu_button,
+        get_chat_menu_button,
+        set_my_default_administrator_rights,
+        get_my_default_administrator_rights,
+        delete_my_commands,
+        answer_inline_query,
+        answer_web_app_query,
+        edit_message_text,
+        edit_message_text_inline,
+        edit_message_caption,
+        edit_message_caption_inline,
+        edit_message_media,
+        edit_message_media_inline,
+        edit_message_reply_markup,
+        edit_message_reply_markup_inline,
+        stop_poll,
+        delete_message,
+        send_sticker,
+        get_sticker_set,
+        get_custom_emoji_stickers,
+        upload_sticker_file,
+        create_new_sticker_set,
+        add_sticker_to_set,
+        set_sticker_position_in_set,
+        delete_sticker_from_set,
+        set_sticker_set_thumb,
+        send_invoice,
+        create_invoice_link,
+        answer_shipping_query,
+        answer_pre_checkout_query,
+        set_passport_data_errors,
+        send_game,
+        set_game_score,
+        set_game_score_inline,
+        get_game_high_scores => fwd_intercept, fty
+    }
+}
@@ -1,135 +1,549 @@
 #![allow(dead_code)]
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+
+use futures::{stream, Stream};
+use teloxide::dispatching::dialogue::serializer::Serializer;
+use teloxide::stop::{mk_stop_token, StopToken};
 use teloxide::types::UpdateKind;
+use teloxide::update_listeners::{StatefulListener, UpdateListener};
 
 use teloxide::{
+    adaptors::DefaultParseMode,
+    dispatching::{
+        dialogue::{GetChatId, Storage},
+        UpdateHandler,
+    },
     dptree::deps,
     prelude::*,
-    types::{ChatId, Me},
+    types::{
+        Chat, ChatId, ChatJoinRequest, ChatMember, ChatMemberKind, ChatMemberUpdated,
+        ChosenInlineResult, Currency, InlineKeyboardButtonKind, InlineKeyboardMarkup, InlineQuery,
+        OrderInfo, Poll, PollAnswer, PreCheckoutQuery, Recipient, ShippingQuery, User,
+    },
 };
 
-use crate::intercept::LAST_SENT_MESSAGE;
-use crate::{bot_schema, get_bot_storage, MyDialogue, State};
+use crate::bot_schema::{dialogue_key, get_dialogue_key};
+use crate::builders::{
+    make_photo_sizes, make_shipping_address, test_date, FakeChat, FakeUser, MessageBuilder,
+};
+use crate::failures::Failure;
+use crate::identity::TestBotIdentity;
+use crate::intercept::SentRequest;
+use crate::migrations::{StateError, VersionedCbor};
+use crate::record::{read_recording, Recorded};
+use crate::scenario::diff;
+use crate::snapshot::{
+    check_snapshot, make_transcript, should_update_snapshots, snapshot_path, Chats,
+};
+use crate::test_bot::TestBot;
+use crate::{
+    add_adaptors, bot_schema, make_dispatcher, HandlerResult, MyDialogue, MyStorage, State,
+};
 
 /*
     Constants
 */
-pub const TEST_USER_ID: i64 = 123456789;
-pub const TEST_GROUP_ID: i64 = -123456789;
+pub use crate::intercept::TEST_USER_ID;
+// The fake responses need the constants too, and they work in the real bot (in dry run), so they
+// live in intercept.rs. This one is here, so the tests get everything from test_utils
 pub const TEST_UPDATE_ID: i32 = 1;
-pub const TEST_USER_NAME: &str = "test_user";
-pub const TEST_USER_FIRST_NAME: &str = "Test";
-pub const TEST_USER_LAST_NAME: &str = "User";
 
 /*
-    The main function
+    The test context
 */
-pub async fn run_update(
-    update: Update,
-    state: State,
-) -> Result<MyDialogue, Box<dyn std::error::Error>> {
-    let bot = get_bot();
-
-    let me: Me = serde_json::from_str(&make_bot_string())?;
-
-    let storage = get_bot_storage().await;
-    let dialogue = MyDialogue::new(storage.clone(), ChatId(TEST_USER_ID));
-    dialogue
-        .update(state)
-        .await
-        .expect("Failed to update dialogue");
-
-    let dependencies = deps![me, bot, storage, update];
-    // If you pass in 'update' as a dependency, it will handle it like a normal update. Very useful
-    // to know!
-
-    let result = bot_schema::schema().dispatch(dependencies).await;
-    // This makes the update go through the schema
-    if let ControlFlow::Break(result) = result {
-        // If it returned `ControlFlow::Break`, everything is fine, but we need to check, if the
-        // handler didn't error out
+
+static NEXT_TEST_USER_ID: AtomicI64 = AtomicI64::new(TEST_USER_ID);
+// Every test gets its own user, so that the tests can run at the same time without touching
+// each other's states. Atomic is like a Mutex, but only for numbers, and a lot simpler
+
+pub type Schema = UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+pub struct TestContext {
+    pub user_id: i64,  // The user that sends the updates in this test
+    pub group_id: i64, // The group where this user sends the group messages
+    pub bot: TestBot,  // Has its own journal of the sent requests
+    pub storage: MyStorage,
+    pub raw_storage: Arc<BytesStorage>, // The same storage, to get to the saved bytes
+    pub identity: TestBotIdentity,      // The bot, as the handlers see it
+    pub user: FakeUser,                 // Who the user_id is, see `as_user` to have a few of them
+    pub group: FakeChat,                // And the group with the group_id
+    pub schema: Schema,                 // What the updates go through, see `with_schema`
+}
+
+impl TestContext {
+    pub fn new() -> Self {
+        // Just make a new one at the start of every test, and everything is clean
+        Self::with_identity(TestBotIdentity::default())
+    }
+
+    pub fn with_identity(identity: TestBotIdentity) -> Self {
+        // The same, but the bot has a different name, id or token (see identity.rs)
+        let user_id = NEXT_TEST_USER_ID.fetch_add(1, Ordering::SeqCst);
+        // The group is a supergroup, so its id starts with "-100", the same as TEST_GROUP_ID for
+        // TEST_USER_ID. The fake responses can tell the supergroups apart only by that
+        let group_id = -1_000_000_000_000 - user_id;
+        // A new storage for every test, nobody else will ever write into it
+        let raw_storage = Arc::new(BytesStorage::default());
+        Self {
+            user_id,
+            group_id,
+            bot: TestBot::new(identity.bot()).with_identity(identity.clone()),
+            storage: raw_storage.clone().erase(),
+            raw_storage,
+            identity,
+            user: FakeUser::new(user_id),
+            group: FakeChat::new(group_id, ChatType::Supergroup),
+            schema: bot_schema::schema::<DefaultParseMode<TestBot>>(),
+        }
+    }
+
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        // The updates go through this schema instead of the one from bot_schema.rs. The calculator
+        // doesn't handle inline queries, payments, polls and so on, but your bot might:
+        //
+        // let ctx = TestContext::new().with_schema(
+        //     dptree::entry().branch(Update::filter_inline_query().endpoint(answer_inline_query)),
+        // );
+        self.schema = schema;
+        self
+    }
+
+    pub fn as_user(&self, user: FakeUser) -> Self {
+        // The same test, with the same bot and storage, but the updates are from another user,
+        // in the same group. Good for the groups with a few people, or for admins vs members:
+        //
+        // let admin = ctx.as_user(ctx.another_user().first_name("Admin"));
+        // admin.dispatch(make_message_update(admin.make_message("/start", true, ChatType::Group)))
+        Self {
+            user_id: user.id,
+            group_id: self.group_id,
+            bot: self.bot.clone(),
+            storage: self.storage.clone(),
+            raw_storage: self.raw_storage.clone(),
+            identity: self.identity.clone(),
+            user,
+            group: self.group.clone(),
+            schema: self.schema.clone(),
+        }
+    }
+
+    pub fn another_user(&self) -> FakeUser {
+        // A user with a new id, that no other test uses
+        FakeUser::new(NEXT_TEST_USER_ID.fetch_add(1, Ordering::SeqCst))
+    }
+
+    /*
+        The main function
+    */
+    pub async fn run_update(
+        &self,
+        update: Update,
+        state: State,
+    ) -> Result<Option<MyDialogue>, Box<dyn std::error::Error>> {
+        // The state is set for the dialogue that the update belongs to, the same way the bot
+        // finds it, so in groups it is the dialogue of this user in this group
+        let dialogue =
+            get_dialogue_key(&update).map(|key| MyDialogue::new(self.storage.clone(), key));
+        if let Some(dialogue) = &dialogue {
+            dialogue
+                .update(state)
+                .await
+                .expect("Failed to update dialogue");
+        }
+        // Inline queries, payments, polls and so on have no chat, so they have no dialogue, and
+        // the state is ignored. They just go through the schema
+
+        self.dispatch(update).await?;
+        Ok(dialogue)
+    }
+
+    pub async fn dispatch(&self, update: Update) -> Result<(), Box<dyn std::error::Error>> {
+        // Just runs the update through the schema, with whatever state is in the storage
+        let result = self.try_dispatch(update).await;
         assert!(result.is_ok(), "Error in handler: {:?}", result);
-    } else {
-        panic!("Unhandled update!");
+        Ok(())
+    }
+
+    pub async fn try_dispatch(&self, update: Update) -> HandlerResult {
+        // The same, but if the handler returns an error, you get it, instead of a panic. This is
+        // the error that the Dispatcher would give to its error handler
+        self.add_to_history(&update);
+        let bot = add_adaptors(self.bot.clone());
+        // The same adaptors as the real bot, but on top of the TestBot, that intercepts everything
+
+        let me = self.identity.me();
+
+        let dependencies = deps![me, bot, self.storage.clone(), update];
+        // If you pass in 'update' as a dependency, it will handle it like a normal update. Very useful
+        // to know!
+
+        let result = self.schema.clone().dispatch(dependencies).await;
+        // This makes the update go through the schema
+        match result {
+            // If it returned `ControlFlow::Break`, some handler got the update, and that's its result
+            ControlFlow::Break(result) => result,
+            ControlFlow::Continue(_) => panic!("Unhandled update!"),
+        }
     }
 
-    Ok(dialogue)
+    pub async fn run_dispatcher(&self, updates: Vec<Update>) {
+        // Unlike `dispatch`, this runs the updates through the real Dispatcher, the same one that
+        // main.rs makes, with all its dependencies and the default error handler. The updates
+        // come from memory instead of telegram, and when they run out, the dispatcher waits for
+        // the handlers to finish, and stops, like it does after ctrl+c
+        for update in &updates {
+            self.add_to_history(update);
+        }
+        let mut dispatcher = make_dispatcher(self.bot.clone(), self.storage.clone());
+        dispatcher
+            .dispatch_with_listener(
+                in_memory_listener(updates),
+                LoggingErrorHandler::with_custom_text("An error from the update listener"),
+            )
+            .await;
+    }
+
+    pub fn fail(&self, failure: Failure) {
+        // The requests that match it will fail, see failures.rs
+        self.bot.failures().lock().unwrap().push(failure);
+    }
+
+    pub async fn replay(&self, path: &Path) {
+        // Runs every update from the recording (see record.rs), and checks that after each of them
+        // the bot sends exactly the same requests, as it did when it was recorded
+        let recording = read_recording(path);
+        let mut steps: Vec<(Update, Vec<Recorded>)> = vec![];
+        for recorded in recording {
+            match recorded {
+                Recorded::Update(update) => steps.push((*update, vec![])),
+                request => {
+                    // The requests from before the first update are skipped, nothing to replay
+                    if let Some((_, requests)) = steps.last_mut() {
+                        requests.push(request);
+                    }
+                }
+            }
+        }
+
+        for (number, (update, recorded_requests)) in steps.into_iter().enumerate() {
+            let chat_id = update.chat_id();
+            let sent_before = self.get_sent_requests().len();
+            self.dispatch(update.clone()).await.unwrap();
+
+            // Only the requests to the same chat are recorded, so only they are compared
+            let sent_requests: Vec<Recorded> = self.get_sent_requests()[sent_before..]
+                .iter()
+                .filter(|request| request_chat_id(request).is_some_and(|id| Some(id) == chat_id))
+                .map(|request| Recorded::Request {
+                    method: request.method.to_string(),
+                    json: request.json.clone(),
+                })
+                .collect();
+            if sent_requests != recorded_requests {
+                panic!(
+                    "The replay of {} diverged at update {} (id {}), the requests are different:\n{}",
+                    path.display(),
+                    number + 1,
+                    update.id,
+                    diff(
+                        &serde_json::to_string_pretty(&recorded_requests).unwrap(),
+                        &serde_json::to_string_pretty(&sent_requests).unwrap()
+                    )
+                );
+            }
+        }
+    }
+
+    fn add_to_history(&self, update: &Update) {
+        // The messages from the update are in the chat now, so the bot can edit or delete them
+        let mut history = self.bot.history().lock().unwrap();
+        match &update.kind {
+            UpdateKind::Message(message)
+            | UpdateKind::EditedMessage(message)
+            | UpdateKind::ChannelPost(message)
+            | UpdateKind::EditedChannelPost(message) => history.add_message(message),
+            UpdateKind::CallbackQuery(CallbackQuery {
+                message: Some(message),
+                ..
+            }) => history.add_message_if_missing(message),
+            _ => {}
+        }
+    }
+
+    pub fn get_dialogue(&self) -> MyDialogue {
+        // The dialogue in the private chat with the bot
+        self.get_dialogue_in(ChatType::Private)
+    }
+
+    pub fn get_dialogue_in(&self, chat: impl IntoFakeChat) -> MyDialogue {
+        // In the groups, every user has their own dialogue, see dialogue_key in bot_schema.rs
+        let chat_id = self.make_chat(chat).id;
+        let key = dialogue_key(chat_id, Some(UserId(self.user_id as u64)), None);
+        MyDialogue::new(self.storage.clone(), key)
+    }
+
+    pub fn get_dialogue_in_topic(&self, thread_id: i32) -> MyDialogue {
+        // Every forum topic is like its own chat, with its own dialogues
+        let key = dialogue_key(
+            ChatId(self.group_id),
+            Some(UserId(self.user_id as u64)),
+            Some(thread_id),
+        );
+        MyDialogue::new(self.storage.clone(), key)
+    }
+
+    pub async fn get_state(&self) -> State {
+        self.get_state_in(ChatType::Private).await
+    }
+
+    pub async fn get_state_in(&self, chat: impl IntoFakeChat) -> State {
+        let dialogue = self.get_dialogue_in(chat);
+        dialogue.get().await.unwrap().unwrap_or_default()
+    }
+
+    pub async fn get_state_in_topic(&self, thread_id: i32) -> State {
+        let dialogue = self.get_dialogue_in_topic(thread_id);
+        dialogue.get().await.unwrap().unwrap_or_default()
+    }
 }
 
 /*
-    Helper functions
+    The storage
 */
-pub fn get_bot_id() -> i64 {
-    // Every token starts with a bot id
-    let token = dotenvy::var("TELOXIDE_TOKEN").unwrap();
-    let parts: Vec<&str> = token.split(':').collect();
-    parts[0].parse::<i64>().unwrap()
-}
 
-pub fn make_bot_string() -> String {
-    format!(
-        r#"{{"id":{bot_id},"is_bot":true,"first_name":"Test","last_name":"Bot","username":"test_bot","language_code":"en","can_join_groups":false,"can_read_all_group_messages":false,"supports_inline_queries":true}}"#,
-        bot_id = get_bot_id()
-    )
+#[derive(Debug, Default)]
+pub struct BytesStorage {
+    // Like teloxide's InMemStorage, but it keeps the states as bytes, made by the same serializer
+    // as redis uses. So the tests also check that every state can be saved and read back, and
+    // they can put the bytes of an old State into it, like the ones that are still in redis
+    states: Mutex<HashMap<ChatId, Vec<u8>>>,
 }
 
-pub fn escape_control_characters(input: &str) -> String {
-    // Serde doesn't love these characters
-    input
-        .chars()
-        .flat_map(|c| match c {
-            '\n' => "\\n".chars().collect::<Vec<char>>(),
-            '\t' => "\\t".chars().collect::<Vec<char>>(),
-            '\\' => "\\\\".chars().collect::<Vec<char>>(),
-            '\"' => "\\\"".chars().collect::<Vec<char>>(),
-            '\'' => "\\'".chars().collect::<Vec<char>>(),
-            _ => vec![c],
+impl Storage<State> for BytesStorage {
+    type Error = StateError;
+
+    fn remove_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<(), StateError>> {
+        Box::pin(async move {
+            self.states.lock().unwrap().remove(&chat_id);
+            Ok(())
         })
-        .collect()
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+        state: State,
+    ) -> BoxFuture<'static, Result<(), StateError>> {
+        Box::pin(async move {
+            let bytes = VersionedCbor.serialize(&state)?;
+            self.states.lock().unwrap().insert(chat_id, bytes);
+            Ok(())
+        })
+    }
+
+    fn get_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<Option<State>, StateError>> {
+        Box::pin(async move {
+            let bytes = self.states.lock().unwrap().get(&chat_id).cloned();
+            bytes
+                .map(|bytes| VersionedCbor.deserialize(&bytes))
+                .transpose()
+        })
+    }
 }
 
-pub fn get_bot() -> Bot {
-    dotenvy::dotenv().ok();
-    Bot::from_env()
+impl TestContext {
+    pub fn set_raw_state(&self, dialogue: &MyDialogue, bytes: Vec<u8>) {
+        // Puts the bytes into the storage as they are, like an older bot saved them into redis
+        let mut states = self.raw_storage.states.lock().unwrap();
+        states.insert(dialogue.chat_id(), bytes);
+    }
+
+    pub fn get_raw_state(&self, dialogue: &MyDialogue) -> Option<Vec<u8>> {
+        // The bytes that the bot saved, the same that it would've saved into redis
+        let states = self.raw_storage.states.lock().unwrap();
+        states.get(&dialogue.chat_id()).cloned()
+    }
 }
 
-pub async fn get_dialogue() -> MyDialogue {
-    let dialogue = MyDialogue::new(get_bot_storage().await, ChatId(TEST_USER_ID));
-    return dialogue;
+impl Default for TestContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-pub async fn get_state() -> State {
-    let dialogue = get_dialogue().await;
-    return dialogue.get().await.unwrap().unwrap();
+/*
+    Helper functions
+*/
+pub async fn replay_recording(path: &Path) -> TestContext {
+    // Replays the recording in a new context, so the states start from scratch, like for a new user
+    let ctx = TestContext::new();
+    ctx.replay(path).await;
+    ctx
 }
 
-pub async fn full_reset_test_user() {
-    // If user has more attached to him, reset it here too if you want
-    let dialogue = get_dialogue().await;
-    dialogue
-        .update(State::default())
-        .await
-        .expect("Failed to update dialogue");
-    *LAST_SENT_MESSAGE.lock().unwrap() = None;
+fn request_chat_id(request: &SentRequest) -> Option<ChatId> {
+    match &request.chat_id {
+        Some(Recipient::Id(chat_id)) => Some(*chat_id),
+        _ => None,
+    }
+}
+
+type InMemoryState = (Vec<Update>, StopToken);
+
+pub fn in_memory_listener(updates: Vec<Update>) -> impl UpdateListener<Err = Infallible> {
+    // An update listener, like the polling, but it just gives out these updates
+    let (stop_token, _) = mk_stop_token(); // The stream ends by itself, nothing to stop
+    StatefulListener::new(
+        (updates, stop_token),
+        updates_stream,
+        |state: &mut InMemoryState| state.1.clone(),
+    )
+}
+
+fn updates_stream(
+    state: &mut InMemoryState,
+) -> impl Stream<Item = Result<Update, Infallible>> + '_ {
+    stream::iter(state.0.drain(..).map(Ok))
+}
+
+pub fn get_bot() -> Bot {
+    // The default test bot, with the fake token
+    TestBotIdentity::default().bot()
 }
 
 /*
     Checking functions (you can add more of them, test just the state, just the text, etc)
 */
 
-pub async fn check_the_state_and_text(state: State, text: &str) {
-    let lock = LAST_SENT_MESSAGE.lock().unwrap().clone();
-    // Without the clone, if something fails, the mutex will be poisoned, making a lot of
-    // PoisonErrors in tests, which is bad
+impl TestContext {
+    pub fn get_sent_requests(&self) -> Vec<SentRequest> {
+        // Every request the bot sent in this test, in order
+        self.bot.journal().lock().unwrap().clone()
+        // Without the clone, if something fails, the mutex will be poisoned, making a lot of
+        // PoisonErrors in tests, which is bad
+    }
+
+    pub fn get_sent_requests_by_method(&self, method: &str) -> Vec<SentRequest> {
+        // e.g. ctx.get_sent_requests_by_method("SendMessage")
+        self.get_sent_requests()
+            .into_iter()
+            .filter(|request| request.method == method)
+            .collect()
+    }
+
+    pub fn get_sent_texts(&self) -> Vec<String> {
+        // The texts of all sent messages and text edits, in order
+        self.get_sent_requests()
+            .iter()
+            .filter_map(|request| request.text().map(|text| text.to_string()))
+            .collect()
+    }
+
+    pub fn check_the_sent_methods(&self, methods: &[&str]) {
+        // Checks that the bot sent exactly these requests, in this order
+        let sent_methods: Vec<&str> = self
+            .get_sent_requests()
+            .iter()
+            .map(|request| request.method)
+            .collect();
+        assert_eq!(sent_methods, methods);
+    }
+
+    pub async fn check_the_state_and_text(&self, state: State, text: &str) {
+        // Checks only the last sent text, if you want to check all of them, use the function below
+        let sent_texts = self.get_sent_texts();
+        assert_eq!(sent_texts.last().expect("No text was sent").as_str(), text);
+        assert_eq!(self.get_state().await, state);
+    }
+
+    pub async fn check_the_state_and_texts(&self, state: State, texts: &[&str]) {
+        assert_eq!(self.get_sent_texts(), texts);
+        assert_eq!(self.get_state().await, state);
+    }
 
-    let last_sent_message = lock.clone().unwrap();
-    assert_eq!(last_sent_message.text().unwrap(), text);
-    assert_eq!(get_state().await, state);
+    pub fn get_last_message(&self) -> SentRequest {
+        // The last message that the bot sent or edited
+        self.get_sent_requests()
+            .into_iter()
+            .rev()
+            .find(|request| request.message_id.is_some())
+            .expect("The bot didn't send any messages")
+    }
+
+    pub fn get_last_keyboard(&self) -> Option<InlineKeyboardMarkup> {
+        self.get_last_message().inline_keyboard()
+    }
+
+    pub fn check_the_keyboard(&self, keyboard: InlineKeyboardMarkup) {
+        // Checks everything in the keyboard of the last message: the rows, the texts, the
+        // callback data, the urls. Make the keyboard the same way the handler does:
+        // InlineKeyboardMarkup::new([[InlineKeyboardButton::callback("Add", "add")]])
+        assert_eq!(self.get_last_keyboard(), Some(keyboard));
+    }
+
+    pub fn check_the_keyboard_labels(&self, rows: &[&[&str]]) {
+        // Only the texts of the buttons, row by row. No keyboard is the same as an empty one
+        let labels: Vec<Vec<String>> = self
+            .get_last_keyboard()
+            .map(|keyboard| {
+                keyboard
+                    .inline_keyboard
+                    .iter()
+                    .map(|row| row.iter().map(|button| button.text.clone()).collect())
+                    .collect()
+            })
+            .unwrap_or_default();
+        assert_eq!(labels, rows);
+    }
+
+    pub fn check_the_thread(&self, thread_id: Option<i32>) {
+        // Every message the bot sent went into this forum topic (None is "General", or no forum).
+        // The edits don't have a thread, they change a message that is already somewhere
+        let threads: Vec<(&str, Option<i32>)> = self
+            .get_sent_requests()
+            .iter()
+            .filter(|request| request.method.starts_with("Send"))
+            .map(|request| (request.method, request.thread_id()))
+            .collect();
+        for (method, sent_thread_id) in &threads {
+            assert_eq!(
+                *sent_thread_id, thread_id,
+                "{method} went into a different thread, all of them: {threads:?}"
+            );
+        }
+    }
+
+    pub async fn check_the_snapshot(&self, name: &str) {
+        // Checks everything the bot sent, and the state, against src/snapshots/<name>.snap
+        // (see snapshot.rs). Use the name of the test, so the names don't clash
+        let chats = Chats {
+            user_id: self.user_id,
+            group_id: self.group_id,
+        };
+        let transcript =
+            make_transcript(&self.get_sent_requests(), &self.get_state().await, &chats);
+        check_snapshot(&snapshot_path(name), &transcript, should_update_snapshots());
+    }
 }
 
 /*
     User input functions
 */
 
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")] // So the scenario files can say `chat: group`
 pub enum ChatType {
     Private,
     Group,
@@ -138,116 +552,305 @@ pub enum ChatType {
     Channel,
 }
 
-pub fn make_chat_string(chat_type: ChatType) -> String {
-    match chat_type {
-        ChatType::Private => format!(
-            r#"{{"id":{user_id},"type":"private","username":"{username}","first_name":"{first_name}","last_name":"{last_name}","bio":null,"has_private_forwards":null,"has_restricted_voice_and_video_messages":null,"emoji_status_custom_emoji_id":null}}"#,
-            user_id = TEST_USER_ID,
-            username = TEST_USER_NAME,
-            first_name = TEST_USER_FIRST_NAME,
-            last_name = TEST_USER_LAST_NAME
-        ),
-        ChatType::Group => format!(
-            r#"{{"id":{chat_id},"type":"group","title":"Test Group"}}"#,
-            chat_id = TEST_GROUP_ID
-        ),
-        ChatType::Supergroup => format!(
-            r#"{{"id":{chat_id},"type":"supergroup","title":"Test Group"}}"#,
-            chat_id = TEST_GROUP_ID
-        ),
-        ChatType::Gigagroup => format!(
-            r#"{{"id":{chat_id},"type":"gigagroup","title":"Test Group"}}"#,
-            chat_id = TEST_GROUP_ID
-        ),
-        ChatType::Channel => format!(
-            r#"{{"id":{chat_id},"type":"channel","title":"Test Channel"}}"#,
-            chat_id = TEST_GROUP_ID
-        ),
-    }
-}
-
-pub fn make_from_string() -> String {
-    // To make the raw strings just a little more readable
-    format!(
-        r#"{{"id":{user_id},"is_bot":false,"first_name":"{first_name}","last_name":"{last_name}","username":"{username}","language_code":"en"}}"#,
-        user_id = TEST_USER_ID,
-        username = TEST_USER_NAME,
-        first_name = TEST_USER_FIRST_NAME,
-        last_name = TEST_USER_LAST_NAME
-    )
+pub trait IntoFakeChat {
+    // Every helper below takes the chat either as a ChatType, and then it's this test's private
+    // chat or group, or as a FakeChat (see builders.rs), if the test needs some other chat:
+    //
+    // let other_group = FakeChat::new(-1000000000042, ChatType::Supergroup).title("Other group");
+    // ctx.make_message("/start", true, &other_group)
+    fn into_fake_chat(self, ctx: &TestContext) -> FakeChat;
 }
 
-pub fn make_message(text: &str, is_command: bool, chat_type: ChatType) -> Message {
-    // Commands are a bit special
-    let command = match is_command {
-        true => format!(
-            r#"{{"type":"bot_command","offset":0,"length":{length}}}"#,
-            length = text.len()
-        ),
-        false => "".to_string(),
-    };
-    // Veeeeeeeeeeeery ugly, but a lot easier than making a full object, and it works perfectly
-    let message_str = format!(
-        r#"{{"message_id":{message_id},"message_thread_id":null,"date":1234567890,"chat":{chat},"via_bot":null,"from":{from},"text":"{message_text}","entities":[{command}],"is_topic_message":false,"is_automatic_forward":false,"has_protected_content":false}}"#,
-        message_text = escape_control_characters(text),
-        chat = make_chat_string(chat_type),
-        from = make_from_string(),
-        message_id = 1,
-        command = command
-    );
-    // Because Message implements serde::Deserialize, we can deserialize it from a string
-    let message: Message = serde_json::from_str(message_str.as_str()).unwrap();
-    return message;
-}
-
-pub fn make_callback_query(data: &str, chat_type: ChatType) -> CallbackQuery {
-    let callback_query_str = format!(
-        r#"{{"id":"{callback_id}","from":{from},"message":{{"message_id":{last_message_id},"message_thread_id":null,"date":1234567890,"chat":{chat},"via_bot":null,"from":{bot},"text":"{last_message_text}","entities":[],"is_topic_message":false,"is_automatic_forward":false,"has_protected_content":false}},"chat_instance":"{chat_instance}","data":"{callback_data}"}}"#,
-        chat = make_chat_string(chat_type),
-        from = make_from_string(),
-        callback_id = 1,
-        last_message_id = 1,
-        last_message_text = "text", // Just in case you need it
-        bot = make_bot_string(),
-        chat_instance = 1,
-        callback_data = data
-    );
-    let callback_query: CallbackQuery = serde_json::from_str(callback_query_str.as_str()).unwrap();
-    return callback_query;
-}
-
-pub fn make_photo(is_media_group: bool, chat_type: ChatType) -> Message {
-    let message_str = format!(
-        r#"{{"message_id":{message_id},"message_thread_id":null,"date":1234567890,"chat":{chat},"via_bot":null,"from":{from},"photo":[{{"file_id":"1234567890","file_unique_id":"1234567890","file_size":932,"width":90,"height":56}},{{"file_id":"1234567890","file_unique_id":"1234567890","file_size":13483,"width":320,"height":200}},{{"file_id":"1234567890","file_unique_id":"1234567890","file_size":60882,"width":800,"height":500}},{{"file_id":"1234567890","file_unique_id":"1234567890","file_size":116270,"width":1280,"height":800}}],"caption_entities":[]{media_group_id},"is_topic_message":false,"is_automatic_forward":false,"has_protected_content":false}}"#,
-        chat = make_chat_string(chat_type),
-        from = make_from_string(),
-        message_id = 1,
-        media_group_id = if is_media_group {
-            ",\"media_group_id\":\"1\""
-        } else {
-            ""
-        },
-    );
-    let message: Message = serde_json::from_str(&message_str).unwrap();
-    return message;
-}
-
-pub fn make_webapp_data(data: &str, chat_type: ChatType) -> Message {
-    let message_str = format!(
-        "{{\"message_id\":{message_id},\"message_thread_id\":null,\"date\":1234567890,\"chat\":{chat},\"via_bot\":null,\"web_app_data\":{{\"data\":\"{data}\",\"button_text\":\"Test button text\"}}}}",
-        message_id = 1,
-        chat = make_chat_string(chat_type),
-        data = escape_control_characters(data)
-    );
-    let message: Message = serde_json::from_str(&message_str).unwrap();
-    return message;
+impl IntoFakeChat for ChatType {
+    fn into_fake_chat(self, ctx: &TestContext) -> FakeChat {
+        match self {
+            ChatType::Private => FakeChat::private(&ctx.user),
+            _ => ctx.group.clone().chat_type(self),
+        }
+    }
+}
+
+impl IntoFakeChat for FakeChat {
+    fn into_fake_chat(self, _ctx: &TestContext) -> FakeChat {
+        self
+    }
+}
+
+impl IntoFakeChat for &FakeChat {
+    fn into_fake_chat(self, _ctx: &TestContext) -> FakeChat {
+        self.clone()
+    }
+}
+
+impl TestContext {
+    // The updates are from this test's user, in this test's chats
+    pub fn make_user(&self) -> User {
+        self.user.clone().build()
+    }
+
+    pub fn make_chat(&self, chat: impl IntoFakeChat) -> Chat {
+        chat.into_fake_chat(self).build()
+    }
+
+    pub fn message_builder(&self, chat: impl IntoFakeChat) -> MessageBuilder {
+        // A message from the test user, add whatever you need to it, and then call .build()
+        MessageBuilder::new(self.make_chat(chat)).from(self.make_user())
+    }
+
+    pub fn topic_message_builder(&self, thread_id: i32) -> MessageBuilder {
+        // The same, but in a topic of the group, that is a forum now. The thread id of a topic is
+        // the id of the message that created it
+        let forum = self
+            .group
+            .clone()
+            .chat_type(ChatType::Supergroup)
+            .forum(true);
+        MessageBuilder::new(forum)
+            .from(self.make_user())
+            .thread_id(thread_id)
+            .topic_message(true)
+    }
+
+    pub fn make_topic_message(&self, text: &str, is_command: bool, thread_id: i32) -> Message {
+        let builder = self.topic_message_builder(thread_id);
+        match is_command {
+            true => builder.command(text),
+            false => builder.text(text),
+        }
+        .build()
+    }
+
+    pub fn make_message(&self, text: &str, is_command: bool, chat: impl IntoFakeChat) -> Message {
+        let builder = self.message_builder(chat);
+        // Commands are a bit special, they need an entity
+        match is_command {
+            true => builder.command(text),
+            false => builder.text(text),
+        }
+        .build()
+    }
+
+    pub fn make_callback_query(&self, data: &str, chat: impl IntoFakeChat) -> CallbackQuery {
+        // The message with the button that the user pressed
+        let message = MessageBuilder::new(self.make_chat(chat))
+            .from(self.identity.user())
+            .text("text") // Just in case you need it
+            .build();
+        CallbackQuery {
+            id: "1".to_string(),
+            from: self.make_user(),
+            message: Some(message),
+            inline_message_id: None,
+            chat_instance: "1".to_string(),
+            data: Some(data.to_string()),
+            game_short_name: None,
+        }
+    }
+
+    pub fn get_sent_message(&self, request: &SentRequest) -> Option<Message> {
+        // The message that the request sent, with all the edits after it. None if it was deleted
+        self.bot.history().lock().unwrap().get_sent_message(request)
+    }
+
+    pub fn find_message_with_button(&self, label: &str) -> Option<SentRequest> {
+        // The newest message that has a button with this text now, the user most likely presses it
+        self.get_sent_requests().into_iter().rev().find(|request| {
+            self.get_sent_message(request)
+                .and_then(|message| message.reply_markup().cloned())
+                .is_some_and(|keyboard| {
+                    keyboard
+                        .inline_keyboard
+                        .iter()
+                        .flatten()
+                        .any(|button| button.text == label)
+                })
+        })
+    }
+
+    pub fn press_button(&self, message: &SentRequest, label: &str) -> CallbackQuery {
+        // Presses the button with this text on a message that the bot sent, e.g.
+        // ctx.press_button(&ctx.get_last_message(), "Add")
+        // The callback query has that message, as it is now, with the same id, like telegram
+        // would do it
+        let pressed_message = self
+            .get_sent_message(message)
+            .unwrap_or_else(|| panic!("The message isn't in the chat anymore: {}", message.json));
+        let keyboard = pressed_message
+            .reply_markup()
+            .unwrap_or_else(|| panic!("The message has no inline keyboard: {}", message.json));
+        let button = keyboard
+            .inline_keyboard
+            .iter()
+            .flatten()
+            .find(|button| button.text == label)
+            .unwrap_or_else(|| panic!("There is no button {:?} in {:#?}", label, keyboard));
+        let InlineKeyboardButtonKind::CallbackData(data) = button.kind.clone() else {
+            panic!(
+                "The button {:?} doesn't send anything to the bot, it's {:?}",
+                label, button.kind
+            );
+        };
+
+        CallbackQuery {
+            id: "1".to_string(),
+            from: self.make_user(),
+            message: Some(pressed_message),
+            inline_message_id: None,
+            chat_instance: "1".to_string(),
+            data: Some(data),
+            game_short_name: None,
+        }
+    }
+
+    pub fn make_photo(&self, is_media_group: bool, chat: impl IntoFakeChat) -> Message {
+        let builder = self.message_builder(chat).photo(make_photo_sizes());
+        match is_media_group {
+            true => builder.media_group_id("1"),
+            false => builder,
+        }
+        .build()
+    }
+
+    pub fn make_webapp_data(&self, data: &str, chat: impl IntoFakeChat) -> Message {
+        MessageBuilder::new(self.make_chat(chat))
+            .web_app_data(data, "Test button text")
+            .build()
+    }
+
+    pub fn make_edited_message(&self, text: &str, chat: impl IntoFakeChat) -> Message {
+        // The message with id 1, but the user changed its text a minute later
+        self.message_builder(chat)
+            .text(text)
+            .edit_date(test_date() + chrono::Duration::minutes(1))
+            .build()
+    }
+
+    pub fn make_channel_post(&self, text: &str) -> Message {
+        // Posts in channels don't have a sender, they are from the channel itself
+        MessageBuilder::new(self.make_chat(ChatType::Channel))
+            .text(text)
+            .build()
+    }
+
+    pub fn make_inline_query(&self, query: &str) -> InlineQuery {
+        // What the user typed after "@test_bot "
+        InlineQuery {
+            id: "1".to_string(),
+            from: self.make_user(),
+            location: None,
+            query: query.to_string(),
+            offset: String::new(),
+            chat_type: Some(teloxide::types::ChatType::Private),
+        }
+    }
+
+    pub fn make_chosen_inline_result(&self, result_id: &str, query: &str) -> ChosenInlineResult {
+        ChosenInlineResult {
+            result_id: result_id.to_string(),
+            from: self.make_user(),
+            location: None,
+            inline_message_id: None, // Only if the result had an inline keyboard
+            query: query.to_string(),
+        }
+    }
+
+    pub fn make_shipping_query(&self, invoice_payload: &str) -> ShippingQuery {
+        ShippingQuery {
+            id: "1".to_string(),
+            from: self.make_user(),
+            invoice_payload: invoice_payload.to_string(),
+            shipping_address: make_shipping_address(),
+        }
+    }
+
+    pub fn make_pre_checkout_query(
+        &self,
+        invoice_payload: &str,
+        total_amount: i32, // In the smallest units, so 100 is 1 dollar
+    ) -> PreCheckoutQuery {
+        PreCheckoutQuery {
+            id: "1".to_string(),
+            from: self.make_user(),
+            currency: Currency::USD,
+            total_amount,
+            invoice_payload: invoice_payload.to_string(),
+            shipping_option_id: None,
+            order_info: OrderInfo::default(),
+        }
+    }
+
+    pub fn make_poll_answer(&self, option_ids: Vec<i32>) -> PollAnswer {
+        // The answer to the poll from make_poll() in builders.rs, it has id "1"
+        PollAnswer {
+            poll_id: "1".to_string(),
+            user: self.make_user(),
+            option_ids,
+        }
+    }
+
+    pub fn make_my_chat_member(
+        &self,
+        chat: impl IntoFakeChat,
+        old: ChatMemberKind,
+        new: ChatMemberKind,
+    ) -> ChatMemberUpdated {
+        // The user changed the status of the bot, like added it to a group, or blocked it
+        // (ChatMemberKind::Banned in private chats)
+        self.make_chat_member_updated(chat, self.identity.user(), old, new)
+    }
+
+    pub fn make_chat_member(
+        &self,
+        chat: impl IntoFakeChat,
+        old: ChatMemberKind,
+        new: ChatMemberKind,
+    ) -> ChatMemberUpdated {
+        // The status of the user changed, like they joined or left the group. The bot gets these
+        // only if it is an admin there
+        self.make_chat_member_updated(chat, self.make_user(), old, new)
+    }
+
+    fn make_chat_member_updated(
+        &self,
+        chat: impl IntoFakeChat,
+        member: User,
+        old: ChatMemberKind,
+        new: ChatMemberKind,
+    ) -> ChatMemberUpdated {
+        ChatMemberUpdated {
+            chat: self.make_chat(chat),
+            from: self.make_user(),
+            date: test_date(),
+            old_chat_member: ChatMember {
+                user: member.clone(),
+                kind: old,
+            },
+            new_chat_member: ChatMember {
+                user: member,
+                kind: new,
+            },
+            invite_link: None,
+        }
+    }
+
+    pub fn make_chat_join_request(&self, chat: impl IntoFakeChat) -> ChatJoinRequest {
+        ChatJoinRequest {
+            chat: self.make_chat(chat),
+            from: self.make_user(),
+            date: test_date(),
+            bio: None,
+            invite_link: None,
+        }
+    }
 }
 
 /*
     If you want to add more messages/callbacks/etc:
     1) Make some handler that handles that type of update
-    2) Add println!(serde_json::to_string(&what_you_want_to_test).unwrap()) to the handler;
-    3) Copy the output and make it into a function like the ones above
+    2) Add println!("{:#?}", what_you_want_to_test) to the handler, to see what is in it
+    3) Make it with the MessageBuilder (most of the media is already there, like
+    `ctx.message_builder(ChatType::Private).video().build()`), or add a new method to it in
+    builders.rs, and make it into a function like the ones above
     4) Contribute it to here, if you want to. In the perfect example, every message type would've been here
 */
 
@@ -268,3 +871,88 @@ pub fn make_callback_query_update(callback_query: CallbackQuery) -> Update {
         kind: UpdateKind::CallbackQuery(callback_query),
     }
 }
+
+pub fn make_edited_message_update(message: Message) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::EditedMessage(message),
+    }
+}
+
+pub fn make_channel_post_update(message: Message) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::ChannelPost(message),
+    }
+}
+
+pub fn make_edited_channel_post_update(message: Message) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::EditedChannelPost(message),
+    }
+}
+
+pub fn make_inline_query_update(inline_query: InlineQuery) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::InlineQuery(inline_query),
+    }
+}
+
+pub fn make_chosen_inline_result_update(chosen_inline_result: ChosenInlineResult) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::ChosenInlineResult(chosen_inline_result),
+    }
+}
+
+pub fn make_shipping_query_update(shipping_query: ShippingQuery) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::ShippingQuery(shipping_query),
+    }
+}
+
+pub fn make_pre_checkout_query_update(pre_checkout_query: PreCheckoutQuery) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::PreCheckoutQuery(pre_checkout_query),
+    }
+}
+
+pub fn make_poll_update(poll: Poll) -> Update {
+    // The bot gets these only about the polls it sent itself, when they change
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::Poll(poll),
+    }
+}
+
+pub fn make_poll_answer_update(poll_answer: PollAnswer) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::PollAnswer(poll_answer),
+    }
+}
+
+pub fn make_my_chat_member_update(chat_member_updated: ChatMemberUpdated) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::MyChatMember(chat_member_updated),
+    }
+}
+
+pub fn make_chat_member_update(chat_member_updated: ChatMemberUpdated) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::ChatMember(chat_member_updated),
+    }
+}
+
+pub fn make_chat_join_request_update(chat_join_request: ChatJoinRequest) -> Update {
+    Update {
+        id: TEST_UPDATE_ID,
+        kind: UpdateKind::ChatJoinRequest(chat_join_request),
+    }
+}
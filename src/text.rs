@@ -1,9 +1,13 @@
 // It's just a good practice to keep all the texts in one place in my opinion
-pub const WHAT_DO_YOU_WANT: &str = "Do you want to add or subtract two numbers?";
+pub const WHAT_DO_YOU_WANT: &str = "What do you want to do with two numbers?";
 pub const ENTER_THE_FIRST_NUMBER: &str = "Enter the first number";
 pub const ENTER_THE_SECOND_NUMBER: &str = "Enter the second number";
 pub const PLEASE_ENTER_A_NUMBER: &str = "Please enter a number";
 pub const PLEASE_SEND_TEXT: &str = "Please send text, not anything else";
 pub const YOUR_RESULT: &str = "Your result: ";
+pub const UNKNOWN_OPERATION: &str = "Sorry, I don't know this operation. Please press a button.";
+pub const NO_CHAT_FOR_THE_BUTTON: &str = "Sorry, I can't count here. Please send /start to me";
+pub const NOT_YOUR_KEYBOARD: &str = "This keyboard isn't yours, send /start to get your own";
+pub const CANT_CALCULATE: &str = "Sorry, I can't calculate that. Please enter another number";
 pub const SORRY_BOT_UPDATED: &str =
     "Sorry, bot updated and we lost where you were. Please try again.";